@@ -0,0 +1,89 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::io::{self, IsTerminal, Write};
+
+/// User name and password given with -u, --user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub user: String,
+    /// Password, or `None` if it should be prompted for
+    pub password: Option<String>,
+}
+
+/// Parse USER[:PASSWORD]
+pub fn parse_credentials(s: &str) -> Result<Credentials, String> {
+    let (user, password) = match s.split_once(':') {
+        Some((user, password)) => (user, Some(password.to_string())),
+        None => (s, None),
+    };
+
+    if user.is_empty() {
+        return Err("User name must not be empty".to_string());
+    }
+
+    Ok(Credentials { user: user.to_string(), password })
+}
+
+impl Credentials {
+    /// Get the password, prompting for it on the terminal if needed
+    ///
+    /// The password is not echoed while typed. If no password was given and
+    /// stdin is not a terminal, this fails, as there is no one to prompt.
+    pub fn password(&self) -> io::Result<String> {
+        if let Some(password) = self.password.as_ref() {
+            return Ok(password.clone());
+        }
+
+        if !io::stdin().is_terminal() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "No password given for user '{}', and stdin is not a terminal to prompt on. \
+                    Use -u {}:PASSWORD, or -u {}: for an empty password.",
+                    self.user,
+                    self.user,
+                    self.user
+                )
+            ));
+        }
+
+        eprint!("Enter password for user '{}': ", self.user);
+        io::stderr().flush()?;
+        rpassword::read_password()
+    }
+}
+
+/// Value of the Authorization header for HTTP Basic authentication
+pub fn basic_authorization(user: &str, password: &str) -> String {
+    format!("Basic {}", STANDARD.encode(format!("{}:{}", user, password)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_credentials() {
+        let credentials = |user: &str, password: Option<&str>| {
+            Ok(Credentials { user: user.to_string(), password: password.map(str::to_string) })
+        };
+        assert_eq!(parse_credentials("user:pass"), credentials("user", Some("pass")));
+        assert_eq!(parse_credentials("user:pa:ss"), credentials("user", Some("pa:ss")));
+        assert_eq!(parse_credentials("user:"), credentials("user", Some("")));
+        assert_eq!(parse_credentials("user"), credentials("user", None));
+        assert!(parse_credentials(":pass").is_err());
+    }
+
+    #[test]
+    fn fails_to_prompt_without_terminal() {
+        let credentials = parse_credentials("user").unwrap();
+        if !io::stdin().is_terminal() {
+            assert_eq!(credentials.password().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(parse_credentials("user:").unwrap().password().unwrap(), "");
+    }
+
+    #[test]
+    fn encodes_basic_authorization() {
+        assert_eq!(basic_authorization("Aladdin", "open sesame"), "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+    }
+}
@@ -0,0 +1,109 @@
+use std::{env, ffi::OsStr};
+
+/// Environment variable that disables color if set to a non-empty value,
+/// see https://no-color.org
+const NO_COLOR_ENV_VAR: &str = "NO_COLOR";
+
+const BOLD_CYAN: &str = "\x1b[1;36m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// When to colorize verbose and trace output, given to --color
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Colorize output to a terminal, unless NO_COLOR is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to colorize output to a stream, `is_terminal` telling whether
+    /// that stream is a terminal
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        use_color(self, env::var_os(NO_COLOR_ENV_VAR).as_deref(), is_terminal)
+    }
+}
+
+/// Whether to colorize output, given the value of NO_COLOR, if set
+///
+/// An explicit "always" or "never" takes precedence over NO_COLOR.
+fn use_color(choice: ColorChoice, no_color: Option<&OsStr>, is_terminal: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_terminal && no_color.is_none_or(OsStr::is_empty),
+    }
+}
+
+/// Applies styles to text, or leaves it as is if color is disabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colors {
+    enabled: bool,
+}
+
+impl Colors {
+    pub fn new(choice: ColorChoice, is_terminal: bool) -> Self {
+        Self { enabled: choice.enabled(is_terminal) }
+    }
+
+    /// Style for names, like those of parameters and record types
+    pub fn name(self, s: &str) -> String {
+        self.paint(BOLD_CYAN, s)
+    }
+
+    /// Style for markers at the start of a line, like "*" and "=>"
+    pub fn marker(self, s: &str) -> String {
+        self.paint(BOLD, s)
+    }
+
+    /// Style for less important details, like the offset in a hex dump
+    pub fn dim(self, s: &str) -> String {
+        self.paint(DIM, s)
+    }
+
+    fn paint(self, style: &str, s: &str) -> String {
+        if self.enabled {
+            format!("{}{}{}", style, s, RESET)
+        } else {
+            s.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_depends_on_terminal() {
+        assert!(use_color(ColorChoice::Auto, None, true));
+        assert!(!use_color(ColorChoice::Auto, None, false));
+    }
+
+    #[test]
+    fn always_and_never_ignore_terminal() {
+        assert!(use_color(ColorChoice::Always, None, false));
+        assert!(!use_color(ColorChoice::Never, None, true));
+    }
+
+    #[test]
+    fn no_color_disables_auto_only() {
+        let no_color = Some(OsStr::new("1"));
+        assert!(!use_color(ColorChoice::Auto, no_color, true));
+        assert!(use_color(ColorChoice::Always, no_color, false));
+        // An empty value is ignored
+        assert!(use_color(ColorChoice::Auto, Some(OsStr::new("")), true));
+    }
+
+    #[test]
+    fn paints_only_if_enabled() {
+        let colors = Colors { enabled: true };
+        assert_eq!(colors.name("A"), "\x1b[1;36mA\x1b[0m");
+        let colors = Colors { enabled: false };
+        assert_eq!(colors.name("A"), "A");
+        assert_eq!(colors.dim("A"), "A");
+    }
+}
@@ -0,0 +1,115 @@
+use clap::Command;
+use std::{ffi::OsString, fs, path::Path};
+use toml::{Table, Value};
+
+/// Config file read if none is given with --config, unless --no-config
+pub const DEFAULT_CONFIG_FILE: &str = "fcgi-cli.toml";
+
+/// Read the config file at `path`, and turn it into command line arguments
+/// for `command`
+///
+/// Keys are long option names, e.g. `root = "/var/www/html"`. An option
+/// that takes no value is given if its value is `true`, or as many times as
+/// an integer value says, for a counting option like --verbose. An array
+/// gives a repeatable option once for each of its elements.
+pub fn config_args(command: &Command, path: &Path) -> Result<Vec<OsString>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| { format!("Failed to read config file {}: {}", path.display(), e) })?;
+    let table: Table = contents
+        .parse()
+        .map_err(|e| { format!("Malformed config file {}: {}", path.display(), e) })?;
+
+    let mut args = Vec::new();
+    for (key, value) in table.iter() {
+        let arg = command
+            .get_arguments()
+            .find(|a| { a.get_long() == Some(key.as_str()) })
+            .ok_or_else(|| { format!("Unknown option \"{}\" in config file {}", key, path.display()) })?;
+        let flag = format!("--{}", key);
+
+        if !arg.get_action().takes_values() {
+            let times = match value {
+                Value::Boolean(set) => usize::from(*set),
+                Value::Integer(n) => usize::try_from(*n).unwrap_or(0),
+                _ => return Err(format!("Option \"{}\" in config file {} takes a boolean", key, path.display())),
+            };
+            args.extend(std::iter::repeat_n(OsString::from(&flag), times));
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                Value::Integer(n) => n.to_string(),
+                Value::Float(n) => n.to_string(),
+                Value::Boolean(b) => b.to_string(),
+                _ => {
+                    return Err(format!("Unsupported value for option \"{}\" in config file {}", key, path.display()))
+                }
+            };
+            args.push(OsString::from(format!("{}={}", flag, value)));
+        }
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+    fn command() -> Command {
+        Command::new("fcgi")
+            .args_override_self(true)
+            .arg(Arg::new("root").long("root"))
+            .arg(Arg::new("header").long("header").action(ArgAction::Append))
+            .arg(Arg::new("silent").long("silent").action(ArgAction::SetTrue))
+            .arg(Arg::new("verbose").long("verbose").action(ArgAction::Count))
+    }
+
+    fn args_from(contents: &str) -> Result<Vec<OsString>, String> {
+        let path = std::env::temp_dir().join(format!(
+            "fcgi-config-{}-{}.toml",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, contents).unwrap();
+        let args = config_args(&command(), &path);
+        fs::remove_file(&path).unwrap();
+        args
+    }
+
+    #[test]
+    fn turns_keys_into_arguments() {
+        let args = args_from("root = \"/srv\"\nheader = [\"A: 1\", \"B: 2\"]\nsilent = true\nverbose = 2\n").unwrap();
+        assert_eq!(
+            args,
+            ["--header=A: 1", "--header=B: 2", "--root=/srv", "--silent", "--verbose", "--verbose"]
+        );
+        assert_eq!(args_from("silent = false\n").unwrap(), Vec::<OsString>::new());
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let mut args = vec![OsString::from("fcgi")];
+        args.extend(args_from("root = \"/srv\"\n").unwrap());
+        args.push("--root=/var/www".into());
+        let matches = command().try_get_matches_from(args).unwrap();
+        assert_eq!(matches.get_one::<String>("root").map(String::as_str), Some("/var/www"));
+    }
+
+    #[test]
+    fn rejects_unknown_and_malformed_options() {
+        assert!(args_from("nonsense = 1\n").unwrap_err().contains("Unknown option \"nonsense\""));
+        assert!(args_from("silent = \"yes\"\n").unwrap_err().contains("takes a boolean"));
+        assert!(args_from("root = \n").unwrap_err().contains("Malformed config file"));
+    }
+}
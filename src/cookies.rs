@@ -0,0 +1,306 @@
+use std::{
+    fs,
+    io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH}
+};
+use url::{Host, Url};
+
+/// Prefix curl uses in cookie jars to mark HttpOnly cookies
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// A cookie, as stored in a Netscape-format cookie jar
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    /// Domain without a leading dot, which is implied by `include_subdomains`
+    pub domain: String,
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    /// Expiry as seconds since the epoch, or 0 for a session cookie
+    pub expires: u64,
+    pub name: String,
+    pub value: String,
+    pub http_only: bool,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires != 0 && self.expires <= now
+    }
+
+    /// Whether the cookie should be sent in a request for `url`
+    fn matches(&self, url: Option<&Url>) -> bool {
+        let Some(url) = url else {
+            // Without a URL, there is nothing to match against
+            return true;
+        };
+
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let domain = self.domain.to_ascii_lowercase();
+        let domain_matches = host == domain
+            || (self.include_subdomains && host.ends_with(&format!(".{}", domain)));
+
+        let path = url.path();
+        let path_matches = path == self.path
+            || (path.starts_with(&self.path)
+                && (self.path.ends_with('/') || path[self.path.len()..].starts_with('/')));
+
+        domain_matches && path_matches && (!self.secure || url.scheme() == "https")
+    }
+
+    /// Parse a line of a Netscape-format cookie jar
+    fn from_jar_line(line: &str) -> Option<Self> {
+        let (http_only, line) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        if line.starts_with('#') || line.trim().is_empty() {
+            return None;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+            return None;
+        };
+
+        Some(Self {
+            domain: domain.trim_start_matches('.').to_string(),
+            include_subdomains: include_subdomains.eq_ignore_ascii_case("TRUE"),
+            path: path.to_string(),
+            secure: secure.eq_ignore_ascii_case("TRUE"),
+            expires: expires.parse().ok()?,
+            name: name.to_string(),
+            value: value.to_string(),
+            http_only,
+        })
+    }
+
+    fn to_jar_line(&self) -> String {
+        let flag = |b: bool| { if b { "TRUE" } else { "FALSE" } };
+        format!(
+            "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            if self.http_only { HTTP_ONLY_PREFIX } else { "" },
+            if self.include_subdomains { "." } else { "" },
+            self.domain,
+            flag(self.include_subdomains),
+            self.path,
+            flag(self.secure),
+            self.expires,
+            self.name,
+            self.value
+        )
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| { d.as_secs() }).unwrap_or_default()
+}
+
+/// Parse the value of a Set-Cookie header received in response to `url`
+///
+/// Only the Domain, Path, Expires, Max-Age, Secure and HttpOnly attributes
+/// are taken into account. A cookie that is already expired is returned as such, so
+/// merging it into a jar removes any cookie it replaces. A cookie for a
+/// domain that the host of `url` does not belong to is rejected.
+pub fn parse_set_cookie(value: &str, url: Option<&Url>) -> Result<Cookie, String> {
+    let mut attributes = value.split(';');
+    let (name, value) = attributes
+        .next()
+        .and_then(|pair| { pair.split_once('=') })
+        .ok_or_else(|| { "expected NAME=VALUE".to_string() })?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("empty cookie name".to_string());
+    }
+
+    let mut cookie = Cookie {
+        domain: url.and_then(Url::host_str).unwrap_or_default().to_string(),
+        include_subdomains: false,
+        path: default_path(url),
+        secure: false,
+        expires: 0,
+        name: name.to_string(),
+        value: value.trim().to_string(),
+        http_only: false,
+    };
+
+    let mut max_age = None;
+    for attribute in attributes {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.');
+                if let Some(host) = url.and_then(Url::host) {
+                    if !domain_matches(&host, domain) {
+                        return Err(format!("domain {} does not match host {}", domain, host));
+                    }
+                }
+                cookie.domain = domain.to_string();
+                cookie.include_subdomains = true;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "expires" => {
+                if let Ok(time) = httpdate::parse_http_date(value) {
+                    // Expiry before the epoch is clamped to 1, which is still
+                    // expired, but not mistaken for a session cookie.
+                    cookie.expires = time.duration_since(UNIX_EPOCH).map(|d| { d.as_secs() }).unwrap_or(0).max(1);
+                }
+            }
+            "max-age" => max_age = value.parse::<i64>().ok(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            _ => {}
+        }
+    }
+
+    // Max-Age takes precedence over Expires
+    if let Some(max_age) = max_age {
+        cookie.expires = if max_age <= 0 { 1 } else { now().saturating_add(max_age as u64) };
+    }
+
+    Ok(cookie)
+}
+
+/// Whether `host` domain-matches `domain`, i.e. is equal to it, or is a
+/// subdomain of it if `host` is a name rather than an IP address
+fn domain_matches(host: &Host<&str>, domain: &str) -> bool {
+    match host {
+        Host::Domain(name) => {
+            let (name, domain) = (name.to_ascii_lowercase(), domain.to_ascii_lowercase());
+            name == domain || name.ends_with(&format!(".{}", domain))
+        }
+        host => host.to_string() == domain,
+    }
+}
+
+/// Default path of a cookie, i.e. the directory of the request path
+fn default_path(url: Option<&Url>) -> String {
+    match url.map(Url::path) {
+        Some(path) if path.starts_with('/') => match path.rfind('/') {
+            Some(0) | None => "/".to_string(),
+            Some(i) => path[..i].to_string(),
+        },
+        _ => "/".to_string(),
+    }
+}
+
+/// Add `cookie` to `jar`, replacing any cookie with the same domain, path and
+/// name. An expired cookie is removed instead.
+pub fn merge(jar: &mut Vec<Cookie>, cookie: Cookie) {
+    jar.retain(|c| {
+        !(c.domain.eq_ignore_ascii_case(&cookie.domain) && c.path == cookie.path && c.name == cookie.name)
+    });
+    if !cookie.is_expired(now()) {
+        jar.push(cookie);
+    }
+}
+
+/// Read a Netscape-format cookie jar. Malformed lines are skipped.
+pub fn read_jar(path: &Path) -> io::Result<Vec<Cookie>> {
+    Ok(fs::read_to_string(path)?.lines().filter_map(Cookie::from_jar_line).collect())
+}
+
+/// Write `cookies` to a Netscape-format cookie jar, leaving out expired ones
+pub fn write_jar(path: &Path, cookies: &[Cookie]) -> io::Result<()> {
+    let now = now();
+    let mut contents = String::from("# Netscape HTTP Cookie File\n\n");
+    for cookie in cookies.iter().filter(|c| { !c.is_expired(now) }) {
+        contents.push_str(&cookie.to_jar_line());
+    }
+    fs::write(path, contents)
+}
+
+/// Value for the Cookie header of a request for `url`, built from the
+/// cookies in `jar` that apply to it
+pub fn cookie_header(jar: &[Cookie], url: Option<&Url>) -> Option<String> {
+    let now = now();
+    let pairs: Vec<String> = jar
+        .iter()
+        .filter(|c| { !c.is_expired(now) && c.matches(url) })
+        .map(|c| { format!("{}={}", c.name, c.value) })
+        .collect();
+    (!pairs.is_empty()).then(|| { pairs.join("; ") })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_set_cookie() {
+        let cookie = parse_set_cookie("id=42; Path=/app; Secure", Some(&url("https://example.com/app/login"))).unwrap();
+        assert_eq!(cookie.domain, "example.com");
+        assert!(!cookie.include_subdomains);
+        assert_eq!(cookie.path, "/app");
+        assert!(cookie.secure);
+        assert_eq!((cookie.name.as_str(), cookie.value.as_str()), ("id", "42"));
+
+        let cookie = parse_set_cookie("id=42", Some(&url("http://example.com/app/login"))).unwrap();
+        assert_eq!(cookie.path, "/app");
+        assert!(parse_set_cookie("id", None).is_err());
+        assert!(parse_set_cookie("=42", None).is_err());
+    }
+
+    #[test]
+    fn accepts_matching_domain() {
+        let request_url = url("http://www.example.com/");
+        let cookie = parse_set_cookie("id=42; Domain=.Example.com", Some(&request_url)).unwrap();
+        assert_eq!(cookie.domain, "Example.com");
+        assert!(cookie.include_subdomains);
+        assert!(parse_set_cookie("id=42; Domain=www.example.com", Some(&request_url)).is_ok());
+        assert!(parse_set_cookie("id=42; Domain=127.0.0.1", Some(&url("http://127.0.0.1/"))).is_ok());
+    }
+
+    #[test]
+    fn rejects_foreign_domain() {
+        let request_url = url("http://www.example.com/");
+        assert!(parse_set_cookie("id=42; Domain=evil.com", Some(&request_url)).is_err());
+        assert!(parse_set_cookie("id=42; Domain=ample.com", Some(&request_url)).is_err());
+        assert!(parse_set_cookie("id=42; Domain=sub.www.example.com", Some(&request_url)).is_err());
+        assert!(parse_set_cookie("id=42; Domain=0.0.1", Some(&url("http://127.0.0.1/"))).is_err());
+    }
+
+    #[test]
+    fn merges_and_matches_cookies() {
+        let request_url = url("http://example.com/app/");
+        let mut jar = Vec::new();
+        merge(&mut jar, parse_set_cookie("a=1; Path=/", Some(&request_url)).unwrap());
+        merge(&mut jar, parse_set_cookie("b=2; Path=/other", Some(&request_url)).unwrap());
+        merge(&mut jar, parse_set_cookie("a=3; Path=/", Some(&request_url)).unwrap());
+        assert_eq!(cookie_header(&jar, Some(&request_url)).as_deref(), Some("a=3"));
+
+        merge(&mut jar, parse_set_cookie("a=; Path=/; Max-Age=0", Some(&request_url)).unwrap());
+        assert_eq!(cookie_header(&jar, Some(&request_url)), None);
+        assert_eq!(jar.len(), 1);
+    }
+
+    #[test]
+    fn round_trips_jar_lines() {
+        for line in [
+            "#HttpOnly_.example.com\tTRUE\t/\tFALSE\t0\tsid\t1",
+            "example.com\tFALSE\t/app\tTRUE\t2000000000\tid\t42",
+        ] {
+            assert_eq!(Cookie::from_jar_line(line).unwrap().to_jar_line(), format!("{}\n", line));
+        }
+        let cookie = Cookie::from_jar_line("#HttpOnly_.example.com\tTRUE\t/\tFALSE\t0\tsid\t1").unwrap();
+        assert_eq!(cookie.domain, "example.com");
+        assert!(cookie.http_only);
+        assert_eq!(Cookie::from_jar_line("# .example.com\tTRUE\t/\tFALSE\t0\tsid\t1"), None);
+    }
+
+    #[test]
+    fn replaces_jar_cookie_regardless_of_leading_dot() {
+        let mut jar = vec![Cookie::from_jar_line(".example.com\tTRUE\t/\tFALSE\t0\tsid\t1").unwrap()];
+        let request_url = url("http://www.example.com/");
+        merge(&mut jar, parse_set_cookie("sid=2; Domain=example.com; Path=/; HttpOnly", Some(&request_url)).unwrap());
+        assert_eq!(jar.len(), 1);
+        assert_eq!(jar[0].value, "2");
+        assert!(jar[0].http_only);
+    }
+}
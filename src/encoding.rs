@@ -0,0 +1,101 @@
+use async_compression::tokio::write::{GzipDecoder, GzipEncoder, ZlibDecoder, ZlibEncoder};
+use std::pin::Pin;
+use tokio::io::{self, AsyncWrite, AsyncWriteExt};
+
+/// Content coding supported for request and response bodies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ContentCoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentCoding {
+    /// Look up the coding named by a Content-Encoding header value
+    ///
+    /// Returns `None` for "identity" and for codings that are not supported.
+    /// Lists of multiple codings are not supported either.
+    pub fn from_header(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(ContentCoding::Gzip),
+            "deflate" => Some(ContentCoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Name of the coding, as used in Content-Encoding
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+        }
+    }
+
+    /// Encode `data` in memory
+    pub async fn encode(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(match self {
+            ContentCoding::Gzip => {
+                let mut encoder = GzipEncoder::new(Vec::new());
+                encoder.write_all(data).await?;
+                encoder.shutdown().await?;
+                encoder.into_inner()
+            }
+            ContentCoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new());
+                encoder.write_all(data).await?;
+                encoder.shutdown().await?;
+                encoder.into_inner()
+            }
+        })
+    }
+
+    /// Wrap `writer`, so that data written to it is decoded first
+    ///
+    /// The returned writer must be shut down to detect a truncated body.
+    pub fn decoder<'a>(self, writer: Pin<Box<dyn AsyncWrite + 'a>>) -> Pin<Box<dyn AsyncWrite + 'a>> {
+        match self {
+            ContentCoding::Gzip => Box::pin(GzipDecoder::new(writer)),
+            // HTTP "deflate" is actually the zlib format
+            ContentCoding::Deflate => Box::pin(ZlibDecoder::new(writer)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn decode(coding: ContentCoding, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        let mut decoder = coding.decoder(Box::pin(&mut decoded));
+        decoder.write_all(data).await?;
+        decoder.shutdown().await?;
+        drop(decoder);
+        Ok(decoded)
+    }
+
+    #[test]
+    fn parses_content_encoding() {
+        assert_eq!(ContentCoding::from_header("gzip"), Some(ContentCoding::Gzip));
+        assert_eq!(ContentCoding::from_header(" X-GZIP "), Some(ContentCoding::Gzip));
+        assert_eq!(ContentCoding::from_header("Deflate"), Some(ContentCoding::Deflate));
+        assert_eq!(ContentCoding::from_header("identity"), None);
+        assert_eq!(ContentCoding::from_header("br"), None);
+        assert_eq!(ContentCoding::from_header("gzip, deflate"), None);
+    }
+
+    #[tokio::test]
+    async fn round_trips_bodies() {
+        let body = b"hello, world\n".repeat(100);
+        for coding in [ContentCoding::Gzip, ContentCoding::Deflate] {
+            let encoded = coding.encode(&body).await.unwrap();
+            assert_ne!(encoded, body);
+            assert_eq!(decode(coding, &encoded).await.unwrap(), body, "{}", coding.name());
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_truncated_body() {
+        let encoded = ContentCoding::Gzip.encode(b"hello, world").await.unwrap();
+        assert!(decode(ContentCoding::Gzip, &encoded[..encoded.len() - 4]).await.is_err());
+    }
+}
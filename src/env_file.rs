@@ -0,0 +1,102 @@
+use crate::headers::is_token;
+
+/// Parse the contents of a dotenv-style file into KEY, VALUE pairs
+///
+/// Blank lines and lines starting with '#' are skipped, as is an "export "
+/// prefix. A value may be enclosed in double quotes, in which case \", \\
+/// and \n are unescaped, or in single quotes, in which case it is taken
+/// literally. An unquoted value ends at a '#' preceded by whitespace, and
+/// is trimmed.
+pub fn parse_env_file(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| { format!("line {}: expected \"KEY=VALUE\"", i + 1) })?;
+
+        let key = key.trim();
+        if !is_token(key) {
+            return Err(format!("line {}: invalid name \"{}\"", i + 1, key));
+        }
+
+        let value = parse_value(value.trim())
+            .ok_or_else(|| { format!("line {}: unterminated quoted value", i + 1) })?;
+        vars.push((key.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+fn parse_value(value: &str) -> Option<String> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut unescaped = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(unescaped),
+                '\\' => match chars.next()? {
+                    'n' => unescaped.push('\n'),
+                    c @ ('"' | '\\') => unescaped.push(c),
+                    c => {
+                        unescaped.push('\\');
+                        unescaped.push(c);
+                    }
+                },
+                c => unescaped.push(c),
+            }
+        }
+        None
+    } else if let Some(quoted) = value.strip_prefix('\'') {
+        quoted.find('\'').map(|end| { quoted[..end].to_string() })
+    } else {
+        let end = value
+            .char_indices()
+            .find(|&(i, c)| { c == '#' && value[..i].ends_with(char::is_whitespace) })
+            .map_or(value.len(), |(i, _)| { i });
+        Some(value[..end].trim_end().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| { (k.to_string(), v.to_string()) }).collect()
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let contents = "# comment\n\nA=1\n  # indented comment\nexport B=2\n";
+        assert_eq!(parse_env_file(contents), Ok(vars(&[("A", "1"), ("B", "2")])));
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let contents = "A=\"a \\\"quoted\\\" # value\\nnext\"\nB='literal \\n $x'\nC=\"\"\n";
+        assert_eq!(
+            parse_env_file(contents),
+            Ok(vars(&[("A", "a \"quoted\" # value\nnext"), ("B", "literal \\n $x"), ("C", "")]))
+        );
+    }
+
+    #[test]
+    fn strips_trailing_comments_from_unquoted_values() {
+        let contents = "A=value # comment\nB=a#b\nC = spaced \n";
+        assert_eq!(parse_env_file(contents), Ok(vars(&[("A", "value"), ("B", "a#b"), ("C", "spaced")])));
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        assert_eq!(parse_env_file("A=1\nnonsense\n"), Err("line 2: expected \"KEY=VALUE\"".to_string()));
+        assert_eq!(parse_env_file("A B=1"), Err("line 1: invalid name \"A B\"".to_string()));
+        assert_eq!(parse_env_file("A=\"open"), Err("line 1: unterminated quoted value".to_string()));
+    }
+}
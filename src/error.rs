@@ -0,0 +1,26 @@
+use std::io;
+use thiserror::Error;
+
+/// Errors of a request that callers may want to tell apart
+///
+/// The message of each variant is the text shown to the user.
+#[derive(Debug, Error)]
+pub enum FcgiCliError {
+    /// Connecting to the server failed, or timed out
+    #[error(transparent)]
+    Connect(io::Error),
+    /// The address of the server is missing or cannot be used
+    #[error("{0}")]
+    Transport(String),
+    /// The response header could not be parsed
+    #[error("{0}")]
+    HeaderParse(String),
+    /// The response has a status at or above the failure threshold
+    #[error("Service returned an error response (code: {0})")]
+    Status(u16),
+    /// The request was aborted, by SIGINT or SIGTERM, or by --fail-early
+    #[error("Interrupted")]
+    Interrupted,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
@@ -0,0 +1,532 @@
+use fastcgi_client::Params;
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+    pin::Pin
+};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const VERSION_1: u8 = 1;
+pub const HEADER_LEN: usize = 8;
+const MAX_CONTENT_LENGTH: usize = 0xffff;
+
+/// Request ID used for all requests, like nginx does
+pub const REQUEST_ID: u16 = 1;
+
+/// Request ID of management records
+const MANAGEMENT_REQUEST_ID: u16 = 0;
+
+/// Flag in FCGI_BEGIN_REQUEST asking the application to keep the connection
+/// open after the request
+pub const KEEP_CONN: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RecordType {
+    BeginRequest = 1,
+    AbortRequest = 2,
+    EndRequest = 3,
+    Params = 4,
+    Stdin = 5,
+    Stdout = 6,
+    Stderr = 7,
+    Data = 8,
+    GetValues = 9,
+    GetValuesResult = 10,
+    UnknownType = 11,
+}
+
+impl RecordType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => RecordType::BeginRequest,
+            2 => RecordType::AbortRequest,
+            3 => RecordType::EndRequest,
+            4 => RecordType::Params,
+            5 => RecordType::Stdin,
+            6 => RecordType::Stdout,
+            7 => RecordType::Stderr,
+            8 => RecordType::Data,
+            9 => RecordType::GetValues,
+            10 => RecordType::GetValuesResult,
+            11 => RecordType::UnknownType,
+            _ => return None,
+        })
+    }
+
+    /// Name of the record type, as used in the specification
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordType::BeginRequest => "FCGI_BEGIN_REQUEST",
+            RecordType::AbortRequest => "FCGI_ABORT_REQUEST",
+            RecordType::EndRequest => "FCGI_END_REQUEST",
+            RecordType::Params => "FCGI_PARAMS",
+            RecordType::Stdin => "FCGI_STDIN",
+            RecordType::Stdout => "FCGI_STDOUT",
+            RecordType::Stderr => "FCGI_STDERR",
+            RecordType::Data => "FCGI_DATA",
+            RecordType::GetValues => "FCGI_GET_VALUES",
+            RecordType::GetValuesResult => "FCGI_GET_VALUES_RESULT",
+            RecordType::UnknownType => "FCGI_UNKNOWN_TYPE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader {
+    pub record_type: u8,
+    pub request_id: u16,
+    pub content_length: u16,
+    pub padding_length: u8,
+}
+
+impl RecordHeader {
+    fn new(record_type: RecordType, request_id: u16, content_length: usize) -> Self {
+        let content_length = content_length.min(MAX_CONTENT_LENGTH) as u16;
+        Self {
+            record_type: record_type as u8,
+            request_id,
+            content_length,
+            padding_length: (content_length.wrapping_neg() & 7) as u8,
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let [id_hi, id_lo] = self.request_id.to_be_bytes();
+        let [len_hi, len_lo] = self.content_length.to_be_bytes();
+        [VERSION_1, self.record_type, id_hi, id_lo, len_hi, len_lo, self.padding_length, 0]
+    }
+
+    pub fn from_bytes(buf: [u8; HEADER_LEN]) -> Self {
+        Self {
+            record_type: buf[1],
+            request_id: u16::from_be_bytes([buf[2], buf[3]]),
+            content_length: u16::from_be_bytes([buf[4], buf[5]]),
+            padding_length: buf[6],
+        }
+    }
+}
+
+/// Role of the application in a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u16)]
+pub enum Role {
+    Responder = 1,
+    Authorizer = 2,
+    Filter = 3,
+}
+
+/// Contents of the FCGI_END_REQUEST record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndRequest {
+    pub app_status: u32,
+    pub protocol_status: u8,
+}
+
+impl EndRequest {
+    /// Description of the protocol status, unless the request completed
+    /// normally (FCGI_REQUEST_COMPLETE)
+    pub fn protocol_status_message(&self) -> Option<String> {
+        let message = match self.protocol_status {
+            0 => return None,
+            1 => "This app can't multiplex [CantMpxConn]".to_string(),
+            2 => "New request rejected; too busy [OVERLOADED]".to_string(),
+            3 => "Role value not known [UnknownRole]".to_string(),
+            status => format!("Unknown protocol status {}", status),
+        };
+        Some(format!("{}; AppStatus: {}", message, self.app_status))
+    }
+}
+
+/// Failure of the connection to the application during a request
+///
+/// This is carried inside the `io::Error` returned, so callers can tell a
+/// misbehaving application apart from local errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionError {
+    /// The connection was reset or closed while sending
+    Reset,
+    /// The connection was closed before FCGI_END_REQUEST was received
+    UnexpectedEof,
+    /// A record was malformed or not expected
+    Framing(String),
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::Reset => write!(f, "Connection reset by peer"),
+            ConnectionError::UnexpectedEof => write!(f, "Connection closed before FCGI_END_REQUEST"),
+            ConnectionError::Framing(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+/// Tag an error on the connection stream with the `ConnectionError` it
+/// amounts to, if any
+fn connection_error(error: Error) -> Error {
+    match error.kind() {
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+            Error::new(error.kind(), ConnectionError::Reset)
+        }
+        ErrorKind::UnexpectedEof => Error::new(error.kind(), ConnectionError::UnexpectedEof),
+        _ => error,
+    }
+}
+
+fn framing_error(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, ConnectionError::Framing(message.into()))
+}
+
+/// Direction in which a record was transferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Callback invoked for every record sent or received, with the record
+/// header and its content
+pub type RecordObserver = Box<dyn FnMut(Direction, &RecordHeader, &[u8])>;
+
+/// Record-level access to a stream
+struct Connection<S> {
+    stream: S,
+    observer: Option<RecordObserver>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    async fn write_record(&mut self, record_type: RecordType, request_id: u16, content: &[u8]) -> io::Result<()> {
+        let header = RecordHeader::new(record_type, request_id, content.len());
+        if let Some(observer) = self.observer.as_mut() {
+            observer(Direction::Sent, &header, content);
+        }
+
+        async {
+            self.stream.write_all(&header.to_bytes()).await?;
+            self.stream.write_all(content).await?;
+            self.stream.write_all(&[0u8; 8][..header.padding_length as usize]).await
+        }.await.map_err(connection_error)
+    }
+
+    /// Send all data from `reader` as a stream of records, including the
+    /// terminating empty record.
+    async fn write_stream<R>(&mut self, record_type: RecordType, request_id: u16, reader: &mut R) -> io::Result<()>
+        where
+            R: AsyncRead + Unpin
+    {
+        let mut buf = vec![0u8; MAX_CONTENT_LENGTH];
+        loop {
+            let n = reader.read(&mut buf).await?;
+            self.write_record(record_type, request_id, &buf[..n]).await?;
+            if n == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> Connection<S> {
+    async fn read_record(&mut self) -> io::Result<(RecordHeader, Vec<u8>)> {
+        let mut buf = [0u8; HEADER_LEN];
+        self.stream.read_exact(&mut buf).await.map_err(connection_error)?;
+        if buf[0] != VERSION_1 {
+            return Err(framing_error(format!("Received record of unsupported version {}", buf[0])));
+        }
+        let header = RecordHeader::from_bytes(buf);
+
+        let mut content = vec![0u8; header.content_length as usize + header.padding_length as usize];
+        self.stream.read_exact(&mut content).await.map_err(connection_error)?;
+        content.truncate(header.content_length as usize);
+
+        if let Some(observer) = self.observer.as_mut() {
+            observer(Direction::Received, &header, &content);
+        }
+
+        Ok((header, content))
+    }
+}
+
+/// Query the application for the values of the named variables, e.g.
+/// FCGI_MPXS_CONNS, using an FCGI_GET_VALUES management record.
+///
+/// Variables the application does not know about are left out of the
+/// result. Applications that do not support management records may never
+/// respond, so callers should apply a timeout.
+pub async fn get_values<S>(stream: S, names: &[&str]) -> io::Result<Vec<(String, String)>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin
+{
+    let mut conn = Connection { stream, observer: None };
+
+    let mut query = Vec::new();
+    for name in names {
+        encode_length(&mut query, name.len());
+        encode_length(&mut query, 0);
+        query.extend_from_slice(name.as_bytes());
+    }
+    conn.write_record(RecordType::GetValues, MANAGEMENT_REQUEST_ID, &query).await?;
+    conn.stream.flush().await?;
+
+    let (header, content) = conn.read_record().await?;
+    match RecordType::from_u8(header.record_type) {
+        Some(RecordType::GetValuesResult) => decode_params(&content),
+        Some(RecordType::UnknownType) => {
+            Err(Error::new(ErrorKind::Unsupported, "Management records are not supported"))
+        }
+        _ => Err(framing_error(format!("Received unexpected record of type {}", header.record_type))),
+    }
+}
+
+/// Client for a single FastCGI request on a connected stream.
+pub struct Client<S> {
+    conn: Connection<S>,
+    role: Role,
+    keep_conn: bool,
+    request_id: u16,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            conn: Connection { stream, observer: None },
+            role: Role::Responder,
+            keep_conn: false,
+            request_id: REQUEST_ID,
+        }
+    }
+
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Ask the application to keep the connection open after the request,
+    /// rather than closing it
+    pub fn keep_conn(mut self, keep_conn: bool) -> Self {
+        self.keep_conn = keep_conn;
+        self
+    }
+
+    /// Send the request under `request_id` instead of the default, which
+    /// must not be the id of management records (0)
+    pub fn request_id(mut self, request_id: u16) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    /// Have `observer` called for every record sent or received
+    pub fn observer(mut self, observer: RecordObserver) -> Self {
+        self.conn.observer = Some(observer);
+        self
+    }
+
+    /// Send the request, returning a stream to read the response from.
+    ///
+    /// The request body is sent in full before any part of the response is
+    /// read. An Authorizer does not receive a body, and `data` is only sent
+    /// to a Filter.
+    pub async fn execute<I, D>(mut self, params: &Params<'_>, body: &mut I, data: &mut D) -> io::Result<ResponseStream<S>>
+        where
+            I: AsyncRead + Unpin,
+            D: AsyncRead + Unpin
+    {
+        let conn = &mut self.conn;
+
+        let mut begin_request = [0u8; 8];
+        begin_request[..2].copy_from_slice(&(self.role as u16).to_be_bytes());
+        if self.keep_conn {
+            begin_request[2] = KEEP_CONN;
+        }
+        conn.write_record(RecordType::BeginRequest, self.request_id, &begin_request).await?;
+
+        let encoded_params = encode_params(params);
+        for chunk in encoded_params.chunks(MAX_CONTENT_LENGTH) {
+            conn.write_record(RecordType::Params, self.request_id, chunk).await?;
+        }
+        conn.write_record(RecordType::Params, self.request_id, &[]).await?;
+
+        if self.role == Role::Authorizer {
+            conn.write_stream(RecordType::Stdin, self.request_id, &mut io::empty()).await?;
+        } else {
+            conn.write_stream(RecordType::Stdin, self.request_id, body).await?;
+        }
+
+        if self.role == Role::Filter {
+            conn.write_stream(RecordType::Data, self.request_id, data).await?;
+        }
+
+        conn.stream.flush().await.map_err(connection_error)?;
+
+        Ok(ResponseStream {
+            conn: self.conn,
+            request_id: self.request_id,
+            stderr: Vec::new(),
+            stderr_writer: None,
+            stderr_length: 0,
+            end_request: None,
+        })
+    }
+}
+
+/// Response to a FastCGI request, read record by record
+///
+/// Output on the FCGI_STDERR stream is collected while reading FCGI_STDOUT,
+/// unless it is forwarded to a writer as it arrives.
+pub struct ResponseStream<S> {
+    conn: Connection<S>,
+    request_id: u16,
+    stderr: Vec<u8>,
+    stderr_writer: Option<Pin<Box<dyn AsyncWrite>>>,
+    stderr_length: u64,
+    end_request: Option<EndRequest>,
+}
+
+impl<S: AsyncRead + Unpin> ResponseStream<S> {
+    /// Read the next chunk of data received on FCGI_STDOUT.
+    ///
+    /// Returns `None` once the request has ended.
+    pub async fn next_stdout(&mut self) -> io::Result<Option<Vec<u8>>> {
+        while self.end_request.is_none() {
+            let (header, content) = self.conn.read_record().await?;
+            if header.request_id != self.request_id {
+                return Err(framing_error(format!("Received record for unexpected request id {}", header.request_id)));
+            }
+
+            match RecordType::from_u8(header.record_type) {
+                Some(RecordType::Stdout) => {
+                    if !content.is_empty() {
+                        return Ok(Some(content));
+                    }
+                }
+                Some(RecordType::Stderr) => {
+                    self.stderr_length += content.len() as u64;
+                    match self.stderr_writer.as_mut() {
+                        Some(writer) => {
+                            writer.write_all(&content).await?;
+                            writer.flush().await?;
+                        }
+                        None => self.stderr.extend(content),
+                    }
+                }
+                Some(RecordType::EndRequest) => {
+                    let end_request = parse_end_request(&content)?;
+                    self.end_request = Some(end_request);
+                }
+                _ => {
+                    return Err(framing_error(format!("Received unexpected record of type {}", header.record_type)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Write output on FCGI_STDERR to `writer` as it arrives, rather than
+    /// collecting it
+    pub fn forward_stderr(&mut self, writer: Pin<Box<dyn AsyncWrite>>) {
+        self.stderr_writer = Some(writer);
+    }
+
+    /// Output received on FCGI_STDERR so far, unless forwarded
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    /// Number of bytes received on FCGI_STDERR so far
+    pub fn stderr_length(&self) -> u64 {
+        self.stderr_length
+    }
+
+    /// Contents of the FCGI_END_REQUEST record, once the request has ended
+    pub fn end_request(&self) -> Option<EndRequest> {
+        self.end_request
+    }
+}
+
+fn parse_end_request(content: &[u8]) -> io::Result<EndRequest> {
+    if content.len() < 5 {
+        return Err(framing_error("Truncated FCGI_END_REQUEST record"));
+    }
+
+    Ok(EndRequest {
+        app_status: u32::from_be_bytes([content[0], content[1], content[2], content[3]]),
+        protocol_status: content[4],
+    })
+}
+
+fn encode_params(params: &Params) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (name, value) in params.iter() {
+        encode_length(&mut buf, name.len());
+        encode_length(&mut buf, value.len());
+        buf.extend_from_slice(name.as_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    }
+    buf
+}
+
+fn decode_params(mut content: &[u8]) -> io::Result<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    while !content.is_empty() {
+        let name_length = decode_length(&mut content)?;
+        let value_length = decode_length(&mut content)?;
+        if content.len() < name_length + value_length {
+            return Err(Error::new(ErrorKind::InvalidData, "Truncated name-value pair"));
+        }
+
+        let (name, rest) = content.split_at(name_length);
+        let (value, rest) = rest.split_at(value_length);
+        params.push((String::from_utf8_lossy(name).into_owned(), String::from_utf8_lossy(value).into_owned()));
+        content = rest;
+    }
+    Ok(params)
+}
+
+fn decode_length(content: &mut &[u8]) -> io::Result<usize> {
+    match content.first() {
+        Some(&b) if b < 0x80 => {
+            *content = &content[1..];
+            Ok(b as usize)
+        }
+        Some(_) if content.len() >= 4 => {
+            let length = u32::from_be_bytes([content[0], content[1], content[2], content[3]]) & 0x7fff_ffff;
+            *content = &content[4..];
+            Ok(length as usize)
+        }
+        _ => Err(Error::new(ErrorKind::InvalidData, "Truncated name-value pair")),
+    }
+}
+
+fn encode_length(buf: &mut Vec<u8>, length: usize) {
+    if length < 0x80 {
+        buf.push(length as u8);
+    } else {
+        buf.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_protocol_status() {
+        let end_request = |protocol_status| { EndRequest { app_status: 7, protocol_status } };
+        assert_eq!(end_request(0).protocol_status_message(), None);
+        assert_eq!(
+            end_request(2).protocol_status_message().as_deref(),
+            Some("New request rejected; too busy [OVERLOADED]; AppStatus: 7")
+        );
+        assert_eq!(
+            end_request(3).protocol_status_message().as_deref(),
+            Some("Role value not known [UnknownRole]; AppStatus: 7")
+        );
+        assert_eq!(
+            end_request(9).protocol_status_message().as_deref(),
+            Some("Unknown protocol status 9; AppStatus: 7")
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Characters that need encoding in a form field value, i.e. all but the
+/// unreserved characters of RFC 3986
+const FORM_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Percent-encode `s` for use in a query string or form body
+pub fn urlencode(s: &str) -> String {
+    utf8_percent_encode(s, FORM_VALUE).to_string()
+}
+
+/// Encode a [NAME=]VALUE form field, as given to --data-urlencode
+///
+/// Only VALUE is encoded. If NAME is empty or absent, just the encoded VALUE
+/// is returned.
+pub fn encode_field(field: &str) -> String {
+    match field.split_once('=') {
+        Some((name, value)) if !name.is_empty() => format!("{}={}", name, urlencode(value)),
+        Some((_, value)) => urlencode(value),
+        None => urlencode(field),
+    }
+}
+
+/// Encode a [NAME=]VALUE query field, as given to --query
+///
+/// Unlike with `encode_field`, NAME is encoded as well.
+pub fn encode_query_field(field: &str) -> String {
+    match field.split_once('=') {
+        Some((name, value)) => format!("{}={}", urlencode(name), urlencode(value)),
+        None => urlencode(field),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_reserved_characters() {
+        assert_eq!(urlencode("a b&c=d/e?f#g+h%i"), "a%20b%26c%3Dd%2Fe%3Ff%23g%2Bh%25i");
+        assert_eq!(urlencode("AZaz09-._~"), "AZaz09-._~");
+        assert_eq!(urlencode("\u{e9}"), "%C3%A9");
+    }
+
+    #[test]
+    fn encodes_field_value_only() {
+        assert_eq!(encode_field("q=a b"), "q=a%20b");
+        assert_eq!(encode_field("a b=c&d"), "a b=c%26d");
+        assert_eq!(encode_field("=x=y"), "x%3Dy");
+        assert_eq!(encode_field("just text"), "just%20text");
+    }
+
+    #[test]
+    fn encodes_query_field_name_and_value() {
+        assert_eq!(encode_query_field("a b=c&d"), "a%20b=c%26d");
+        assert_eq!(encode_query_field("flag"), "flag");
+    }
+}
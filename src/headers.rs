@@ -1,31 +1,111 @@
-use std::collections::HashMap;
-
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while, take_while1},
+    bytes::complete::{tag, take, take_while1},
     character::complete::{space0, line_ending},
-    combinator::{recognize, map},
+    combinator::{map, verify},
     error::{make_error, ErrorKind, Error},
     IResult,
-    multi::{fold_many1, many0},
-    sequence::{separated_pair, terminated, delimited}, Finish
+    multi::{fold_many0, fold_many1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated}, Finish
 };
 
-pub fn parse_headers(input: &[u8]) -> Result<(&[u8], HashMap<String, String>), Error<&[u8]>> {
+/// Response header fields in order of appearance
+///
+/// Field names are stored in lowercase for lookups, and in their original
+/// casing for output. Repeated fields, like Set-Cookie, are all retained.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+    /// Names as received, in the same order as `fields`
+    original_names: Vec<String>,
+}
+
+impl Headers {
+    /// Value of the first field named `name`, which must be lowercase
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(n, _)| { n == name }).map(|(_, v)| { v.as_str() })
+    }
+
+    /// All fields as name, value pairs, with names as received
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.original_names.iter().zip(self.fields.iter()).map(|(n, (_, v))| { (n.as_str(), v.as_str()) })
+    }
+
+    /// Values of all fields named `name`, which must be lowercase
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.fields.iter().filter(move |(n, _)| { n == name }).map(|(_, v)| { v.as_str() })
+    }
+}
+
+/// Character set used to decode header values
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HeaderCharset {
+    #[default]
+    Latin1,
+    Utf8,
+}
+
+impl HeaderCharset {
+    fn decode(self, s: &[u8]) -> String {
+        match self {
+            HeaderCharset::Latin1 => latin1_to_string(s),
+            HeaderCharset::Utf8 => String::from_utf8_lossy(s).into_owned(),
+        }
+    }
+
+    /// Encode `s` for output as part of a header block
+    ///
+    /// Characters that cannot be represented in latin1 are replaced by '?'.
+    pub fn encode(self, s: &str) -> Vec<u8> {
+        match self {
+            HeaderCharset::Latin1 => s.chars().map(|c| { u8::try_from(c).unwrap_or(b'?') }).collect(),
+            HeaderCharset::Utf8 => s.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Options for parsing response headers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Accept a line starting with SP or HT as continuation of the value of
+    /// the previous field (obsolete line folding). Each fold is replaced by a
+    /// single SP.
+    pub allow_obs_fold: bool,
+    /// Character set to decode values with
+    pub charset: HeaderCharset,
+    /// Maximum size of the header block in bytes, including the empty line
+    /// ending it
+    pub max_size: Option<usize>,
+}
+
+/// Parse the header block at the start of `input`, returning the remaining
+/// input and the parsed headers.
+///
+/// A header block larger than `options.max_size`, or one that is not
+/// terminated within that size, fails with `ErrorKind::TooLarge`.
+pub fn parse_headers(input: &[u8], options: ParseOptions) -> Result<(&[u8], Headers), Error<&[u8]>> {
+    if let Some(max_size) = options.max_size {
+        if find_header_end(input).unwrap_or(input.len()) > max_size {
+            return Err(make_error(input, ErrorKind::TooLarge));
+        }
+    }
+
     terminated(
         fold_many1(
-            generic_field,
-            HashMap::new,
-            |mut acc: HashMap<String, String>, kv: (&[u8], &[u8])| {
+            generic_field(options.allow_obs_fold),
+            Headers::default,
+            |mut acc: Headers, kv: Field| {
                 // We expect headers to be in ASCII, so let's prevent unnecessary
                 // UTF-8 decoding. However, we do not check whether all bytes are
                 // actually valid ASCII, instead we assume ISO-8859-1 (latin1)
                 // encoding, which is a superset of ASCII and a subset of Unicode.
-                acc.insert(
-                    latin1_to_string(kv.0)
-                        .to_ascii_lowercase(),
-                    latin1_to_string(kv.1)
-                );
+                // Values may be decoded as UTF-8 instead, if so requested.
+                let name = latin1_to_string(kv.0);
+                acc.fields.push((
+                    name.to_ascii_lowercase(),
+                    options.charset.decode(&kv.1)
+                ));
+                acc.original_names.push(name);
                 acc
             }
         ),
@@ -33,26 +113,105 @@ pub fn parse_headers(input: &[u8]) -> Result<(&[u8], HashMap<String, String>), E
     )(input).finish()
 }
 
-fn generic_field(input: &[u8]) -> IResult<&[u8], (&[u8], &[u8])> {
+/// Find the end of the header block, i.e. the offset right after the empty
+/// line separating the headers from the body.
+///
+/// This only scans for the separator; it does not validate the headers.
+pub fn find_header_end(data: &[u8]) -> Option<usize> {
+    let mut line_start = 0;
+    for (i, &b) in data.iter().enumerate() {
+        if b == b'\n' {
+            let line = &data[line_start..i];
+            if line.is_empty() || line == b"\r" {
+                return Some(i + 1);
+            }
+            line_start = i + 1;
+        }
+    }
+    None
+}
+
+/// Remove the fields named in `names` from a raw header block, including any
+/// continuation lines. Names are matched case-insensitively.
+///
+/// Everything else, including line endings, is left untouched.
+pub fn remove_header_fields(block: &[u8], names: &[&str]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(block.len());
+    let mut removing = false;
+    for line in block.split_inclusive(|&b| { b == b'\n' }) {
+        let is_continuation = line.first().is_some_and(|&b| { b == b' ' || b == b'\t' });
+        if !is_continuation {
+            let name = line.split(|&b| { b == b':' }).next().unwrap_or_default();
+            removing = line.contains(&b':')
+                && names.iter().any(|n| { n.as_bytes().eq_ignore_ascii_case(name) });
+        }
+        if !removing {
+            out.extend_from_slice(line);
+        }
+    }
+    out
+}
+
+/// Make every line of a raw header block end in CRLF, as HTTP requires,
+/// where the application may have used a bare LF
+pub fn crlf_line_endings(block: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(block.len());
+    for line in block.split_inclusive(|&b| { b == b'\n' }) {
+        match line.strip_suffix(b"\n") {
+            Some(content) => {
+                out.extend_from_slice(content.strip_suffix(b"\r").unwrap_or(content));
+                out.extend_from_slice(b"\r\n");
+            }
+            None => out.extend_from_slice(line),
+        }
+    }
+    out
+}
+
+/// Map a request header name to the name of the corresponding CGI variable.
+///
+/// Content-Type and Content-Length map to their CGI metavariables, all other
+/// headers become protocol variables named HTTP_*, with dashes replaced by
+/// underscores.
+pub fn cgi_param_name(header_name: &str) -> String {
+    let name = header_name.to_ascii_uppercase().replace('-', "_");
+    match name.as_str() {
+        "CONTENT_TYPE" | "CONTENT_LENGTH" => name,
+        _ => format!("HTTP_{}", name),
+    }
+}
+
+/// Raw name and unescaped value of a header field
+type Field<'a> = (&'a [u8], Vec<u8>);
+
+fn generic_field<'a>(allow_obs_fold: bool) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Field<'a>> {
     terminated(
         separated_pair(
             token,
             terminated(tag(b":"), space0),
-            field_content //field_value
+            field_content(allow_obs_fold) //field_value
         ),
         line_ending
-    )(input)
+    )
 }
 
 fn token(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    take_while1(|b: u8| {
-        !b"()<>@,;:\\\"/[]?={} ".contains(&b)
-            && !(b as char).is_ascii_control()
-    })(input)
+    take_while1(is_token_char)(input)
+}
+
+fn is_token_char(b: u8) -> bool {
+    !b"()<>@,;:\\\"/[]?={} ".contains(&b)
+        && !(b as char).is_ascii_control()
+}
+
+/// Whether `s` is a valid token, i.e. a non-empty string without separators
+/// or control characters
+pub fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(is_token_char)
 }
 
 fn separator(input: &[u8]) -> IResult<&[u8], &u8> {
-    if input.len() > 0 && b"()<>@,;:\\\"/[]?={} \t".contains(&input[0]) {
+    if !input.is_empty() && b"()<>@,;:\\\"/[]?={} \t".contains(&input[0]) {
         Ok((&input[1..], &input[0]))
     } else {
         // Probably this is not the way to do it, but it does the job for now.
@@ -60,37 +219,204 @@ fn separator(input: &[u8]) -> IResult<&[u8], &u8> {
     }
 }
 
-fn quoted_string(input: &[u8]) -> IResult<&[u8], &[u8]> {
+/// Content of a quoted string, with any quoted-pairs unescaped
+fn quoted_string(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
     delimited(
         tag("\""),
-        take_while(|b: u8| {
-            b != b'"' && (b == b'\t' || !(b as char).is_ascii_control())
-        }),
+        fold_many0(
+            alt((quoted_pair, qdtext)),
+            Vec::new,
+            |mut acc: Vec<u8>, part: &[u8]| {
+                acc.extend_from_slice(part);
+                acc
+            }
+        ),
         tag("\"")
     )(input)
 }
 
-fn field_content(input: &[u8]) -> IResult<&[u8], &[u8]> {
+fn qdtext(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    take_while1(|b: u8| {
+        b != b'"' && b != b'\\' && (b == b'\t' || !(b as char).is_ascii_control())
+    })(input)
+}
+
+/// A backslash followed by the character it escapes, which is returned
+fn quoted_pair(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    preceded(
+        tag("\\"),
+        verify(take(1usize), |c: &[u8]| { c[0] != b'\r' && c[0] != b'\n' })
+    )(input)
+}
+
+/// Field value, with quoted-pairs in quoted strings unescaped. The quotes
+/// delimiting a quoted string are retained.
+fn field_content<'a>(allow_obs_fold: bool) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Vec<u8>> {
     // CGI/1.1 spec includes NL in LWSP, and allows LWSP in headers....
     // Further down it states that headers must be single line, which
-    // seems contradictory. Also, correctly parsing a header containing
-    // NL seems impossible, so let's assume LWSP should not include NL
-    // at all.
+    // seems contradictory. By default, we assume LWSP should not include
+    // NL at all. Folded lines are only accepted when explicitly allowed.
     //
     // And then it follows that we do not need to state LWSP explicitly,
     // as the remaining characters HT and SP are separators.
 
-    recognize(
-        many0(
-            alt((
-                token,
-                map(separator, std::slice::from_ref),
-                quoted_string
-            ))
-        )
-    )(input)
+    fold_many0(
+        alt((
+            map(token, <[u8]>::to_vec),
+            // Before separator, which would otherwise match the opening quote
+            map(quoted_string, |s| { [&b"\""[..], &s, &b"\""[..]].concat() }),
+            map(separator, |b| { vec![*b] }),
+            obs_fold(allow_obs_fold)
+        )),
+        Vec::new,
+        |mut acc: Vec<u8>, part: Vec<u8>| {
+            acc.extend(part);
+            acc
+        }
+    )
+}
+
+/// A line ending followed by whitespace, replaced by a single SP. Fails
+/// unless `allowed`.
+fn obs_fold<'a>(allowed: bool) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Vec<u8>> {
+    move |input: &'a [u8]| {
+        if !allowed {
+            return Err(nom::Err::Error(make_error(input, ErrorKind::Verify)));
+        }
+
+        map(
+            pair(line_ending, take_while1(|b: u8| { b == b' ' || b == b'\t' })),
+            |_| { vec![b' '] }
+        )(input)
+    }
 }
 
 pub fn latin1_to_string(s: &[u8]) -> String {
     s.iter().map(|&c| c as char).collect()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> Headers {
+        parse_headers(input, ParseOptions::default()).unwrap().1
+    }
+
+    #[test]
+    fn finds_header_end() {
+        assert_eq!(find_header_end(b"A: 1\r\n\r\nbody"), Some(8));
+        assert_eq!(find_header_end(b"A: 1\n\nbody"), Some(6));
+        assert_eq!(find_header_end(b"\r\nbody"), Some(2));
+        assert_eq!(find_header_end(b"A: 1\r\nB: 2"), None);
+    }
+
+    #[test]
+    fn maps_header_names_to_cgi_params() {
+        assert_eq!(cgi_param_name("Accept"), "HTTP_ACCEPT");
+        assert_eq!(cgi_param_name("x-forwarded-for"), "HTTP_X_FORWARDED_FOR");
+        assert_eq!(cgi_param_name("Content-Type"), "CONTENT_TYPE");
+        assert_eq!(cgi_param_name("content-length"), "CONTENT_LENGTH");
+        assert_eq!(cgi_param_name("Content-Encoding"), "HTTP_CONTENT_ENCODING");
+    }
+
+    #[test]
+    fn parses_fields_and_returns_body() {
+        let (body, headers) = parse_headers(
+            b"Status: 404 Not Found\r\nContent-Type: text/html\r\n\r\n<p>",
+            ParseOptions::default()
+        ).unwrap();
+        assert_eq!(body, b"<p>");
+        assert_eq!(headers.get("status"), Some("404 Not Found"));
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("location"), None);
+    }
+
+    #[test]
+    fn accepts_bare_lf() {
+        let headers = parse(b"Content-Type: text/plain\n\n");
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+    }
+
+    #[test]
+    fn retains_repeated_fields() {
+        let headers = parse(b"Set-Cookie: a=1\r\nX-Other: x\r\nset-cookie: b=2\r\n\r\n");
+        assert_eq!(headers.get("set-cookie"), Some("a=1"));
+        assert_eq!(headers.get_all("set-cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+        let names: Vec<_> = headers.iter().map(|(n, _)| { n }).collect();
+        assert_eq!(names, ["Set-Cookie", "X-Other", "set-cookie"]);
+    }
+
+    #[test]
+    fn checks_tokens() {
+        assert!(is_token("GET"));
+        assert!(is_token("X-Custom_1"));
+        assert!(!is_token(""));
+        assert!(!is_token("A B"));
+        assert!(!is_token("a:b"));
+    }
+
+    #[test]
+    fn removes_header_fields() {
+        let block = b"Status: 200 OK\r\nContent-Type: text/plain\r\nX-Long: a\r\n b\r\nstatus: 201\r\n\r\n";
+        assert_eq!(
+            remove_header_fields(block, &["Status", "x-long"]),
+            b"Content-Type: text/plain\r\n\r\n"
+        );
+        assert_eq!(remove_header_fields(block, &[]), block);
+    }
+
+    #[test]
+    fn unescapes_quoted_pairs() {
+        let headers = parse(b"Content-Disposition: attachment; filename=\"a \\\"b\\\" \\\\c.txt\"\r\n\r\n");
+        assert_eq!(headers.get("content-disposition"), Some("attachment; filename=\"a \"b\" \\c.txt\""));
+    }
+
+    #[test]
+    fn rejects_obs_fold_by_default() {
+        assert!(parse_headers(b"X-Long: a\r\n b\r\n\r\n", ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn accepts_obs_fold_if_allowed() {
+        let options = ParseOptions { allow_obs_fold: true, ..ParseOptions::default() };
+        let (_, headers) = parse_headers(b"X-Long: a\r\n  b\r\n\tc\r\nX-Next: d\r\n\r\n", options).unwrap();
+        assert_eq!(headers.get("x-long"), Some("a b c"));
+        assert_eq!(headers.get("x-next"), Some("d"));
+    }
+
+    #[test]
+    fn decodes_charset() {
+        let input = "X-Title: Caf\u{e9}\r\n\r\n".as_bytes();
+        assert_eq!(parse(input).get("x-title"), Some("Caf\u{c3}\u{a9}"));
+
+        let options = ParseOptions { charset: HeaderCharset::Utf8, ..ParseOptions::default() };
+        let (_, headers) = parse_headers(input, options).unwrap();
+        assert_eq!(headers.get("x-title"), Some("Caf\u{e9}"));
+
+        let (_, headers) = parse_headers(b"X-Title: \xff\r\n\r\n", options).unwrap();
+        assert_eq!(headers.get("x-title"), Some("\u{fffd}"));
+    }
+
+    #[test]
+    fn encodes_charset() {
+        assert_eq!(HeaderCharset::Latin1.encode("\u{e9}\u{20ac}"), b"\xe9?");
+        assert_eq!(HeaderCharset::Utf8.encode("\u{e9}"), "\u{e9}".as_bytes());
+    }
+
+    #[test]
+    fn enforces_max_size() {
+        let input = b"X-A: 1\r\n\r\nbody";
+        let options = |max_size| { ParseOptions { max_size: Some(max_size), ..ParseOptions::default() } };
+        assert!(parse_headers(input, options(10)).is_ok());
+        assert_eq!(parse_headers(input, options(9)).unwrap_err().code, ErrorKind::TooLarge);
+        // Unterminated within the limit
+        assert_eq!(parse_headers(b"X-A: 1\r\nX-B: 2", options(8)).unwrap_err().code, ErrorKind::TooLarge);
+    }
+
+    #[test]
+    fn converts_line_endings_to_crlf() {
+        assert_eq!(crlf_line_endings(b"A: 1\nB: 2\r\n\n"), b"A: 1\r\nB: 2\r\n\r\n");
+        assert_eq!(crlf_line_endings(b"A: 1\r\npartial"), b"A: 1\r\npartial");
+    }
+}
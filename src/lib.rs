@@ -0,0 +1,3489 @@
+//! FastCGI client, as used by the `fcgi` command line tool
+//!
+//! `run` performs the requests described by a parsed `Cli`, exactly like the
+//! tool does. To send a request from other code, use `FcgiRequestBuilder`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use auth::{basic_authorization, parse_credentials, Credentials};
+use clap::{builder::ArgPredicate, ArgAction, CommandFactory, Parser};
+use clap_complete::Shell;
+use config::{config_args, DEFAULT_CONFIG_FILE};
+use cookies::{cookie_header, merge, parse_set_cookie, read_jar, write_jar, Cookie};
+use fastcgi_client::Params;
+use fcgi::{get_values, Client, ConnectionError, Direction, RecordHeader, ResponseStream};
+use encoding::ContentCoding;
+use env_file::parse_env_file;
+use form::{encode_field, encode_query_field};
+use headers::{
+    cgi_param_name,
+    crlf_line_endings,
+    find_header_end,
+    is_token,
+    parse_headers,
+    remove_header_fields,
+    HeaderCharset,
+    ParseOptions
+};
+use mime::guess_content_type;
+use multipart::{parse_form_part, FormPart, Multipart};
+use mux::{supports_multiplexing, Multiplexer};
+use percent_encoding::percent_decode_str;
+use std::{
+    borrow::{Borrow, Cow},
+    collections::HashSet,
+    env,
+    ffi::OsString,
+    fs::File,
+    future::Future,
+    io::{Cursor, IsTerminal},
+    net::IpAddr,
+    ops::RangeInclusive,
+    os::fd::AsFd,
+    path::{Path, PathBuf},
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, OnceLock},
+    time::{Duration, UNIX_EPOCH}
+};
+use tokio::{
+    fs::OpenOptions,
+    io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt},
+    task::{JoinSet, LocalSet},
+    time
+};
+use tokio_util::sync::CancellationToken;
+use color::{ColorChoice, Colors};
+use prefix::LinePrefixWriter;
+use progress::{Progress, ProgressReader};
+use regex::Regex;
+use report::{headers_json, json_report, HeaderDumpFormat, OutputFormat};
+use shared::SharedWriter;
+use trace::Trace;
+use transport::{
+    socket_activation_transport,
+    tcp_transport,
+    unix_transport,
+    Stream
+};
+use url::Url;
+use url_template::{parse_range, parse_url_template, UrlTemplate};
+use write_out::{render, Stats, Target};
+
+mod auth;
+mod color;
+mod config;
+mod cookies;
+mod encoding;
+mod env_file;
+mod error;
+mod fcgi;
+mod form;
+mod headers;
+mod mime;
+mod multipart;
+mod mux;
+mod prefix;
+mod progress;
+mod report;
+mod request;
+mod shared;
+mod trace;
+mod transport;
+mod url_template;
+mod write_out;
+
+pub use error::FcgiCliError;
+pub use fcgi::Role;
+pub use headers::Headers;
+pub use request::{FcgiRequestBuilder, Response};
+pub use transport::{resolve_transport, Transport};
+
+/// Environment variable to take the address from, if none is given
+const ADDRESS_ENV_VAR: &str = "FCGI_ADDRESS";
+
+/// REMOTE_ADDR to send if not given otherwise, as if requested locally
+const DEFAULT_REMOTE_ADDR: &str = "127.0.0.1";
+
+/// Output file name for -O, --remote-name with --default-filename
+const DEFAULT_FILE_NAME: &str = "index.html";
+
+/// HTTP_ACCEPT_ENCODING to send unless --no-accept-encoding, listing the
+/// codings that are decompressed
+const DEFAULT_ACCEPT_ENCODING: &str = "gzip, deflate";
+
+/// Request methods accepted without --allow-custom-method: those of HTTP
+/// (RFC 9110 and RFC 5789) and WebDAV (RFC 4918)
+const KNOWN_METHODS: &[&str] = &[
+    "GET",
+    "HEAD",
+    "POST",
+    "PUT",
+    "DELETE",
+    "CONNECT",
+    "OPTIONS",
+    "TRACE",
+    "PATCH",
+    "PROPFIND",
+    "PROPPATCH",
+    "MKCOL",
+    "COPY",
+    "MOVE",
+    "LOCK",
+    "UNLOCK",
+];
+
+/// Maximum number of bytes to allocate up front for a captured response body
+const MAX_PREALLOCATION: u64 = 16 << 20;
+
+/// Time to wait for a response to a management record
+const MANAGEMENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+const CGI_META_VARS: &[&str] = &[
+    "AUTH_TYPE",
+    "CONTENT_LENGTH",
+    "CONTENT_TYPE",
+    "GATEWAY_INTERFACE",
+    "PATH_INFO",
+    "PATH_TRANSLATED",
+    "QUERY_STRING",
+    "REMOTE_ADDR",
+    "REMOTE_HOST",
+    "REMOTE_IDENT",
+    "REMOTE_USER",
+    "REQUEST_METHOD",
+    "SCRIPT_NAME",
+    "SERVER_NAME",
+    "SERVER_PORT",
+    "SERVER_PROTOCOL",
+    "SERVER_SOFTWARE",
+];
+
+/// Parameters the FastCGI specification leaves out of requests in the
+/// authorizer role, as they describe the body and script of the request being
+/// authorized, rather than of the authorizer
+const AUTHORIZER_EXCLUDED_PARAMS: &[&str] = &["CONTENT_LENGTH", "PATH_INFO", "PATH_TRANSLATED", "SCRIPT_NAME"];
+
+/// Variables that web servers commonly set for CGI programs in addition to
+/// the CGI/1.1 meta-variables, and that FastCGI applications rely on. These
+/// are passed in --cgi mode, for the bridge to be transparent.
+const CGI_BRIDGE_VARS: &[&str] = &[
+    "CONTEXT_DOCUMENT_ROOT",
+    "CONTEXT_PREFIX",
+    "DOCUMENT_ROOT",
+    "HTTPS",
+    "REDIRECT_STATUS",
+    "REDIRECT_URL",
+    "REMOTE_PORT",
+    "REQUEST_SCHEME",
+    "REQUEST_URI",
+    "SCRIPT_FILENAME",
+    "SCRIPT_URI",
+    "SCRIPT_URL",
+    "SERVER_ADDR",
+    "SERVER_ADMIN",
+    "SERVER_SIGNATURE",
+];
+
+#[derive(Parser, Debug, Clone)]
+#[command(name = "FastCGI CLI")]
+#[command(args_override_self = true)]
+#[command(author = "Harry T. Vennik <htvennik@gmail.com>")]
+#[command(version = env!("CARGO_PKG_VERSION"))]
+#[command(about = "Send request to FastCGI server.")]
+#[command(long_about = "CLI tool to interact with a FastCGI server directly. Also deployable as a CGI-to-FastCGI bridge.")]
+pub struct Cli {
+    /**
+        Address of FastCGI server
+
+        May be either HOST:PORT or a PATH to a unix socket. On Linux, @NAME
+        refers to a unix socket in the abstract namespace. Use fd://N to
+        talk over an already connected socket inherited as file descriptor N.
+
+        If omitted, the address is taken from the FCGI_ADDRESS environment
+        variable. Failing that, the socket passed by systemd socket
+        activation (LISTEN_FDS) is used, which requires Accept=yes in the
+        socket unit, as it must be a connected socket. When omitting ADDRESS, URLs must
+        include a scheme, so they are not mistaken for an address.
+    */
+    address: Option<String>,
+
+    /// Connect to ADDRESS over TCP, even if it looks like a path
+    ///
+    /// Port 9000 is assumed if ADDRESS does not include a port.
+    #[arg(long = "tcp", conflicts_with = "force_unix")]
+    force_tcp: bool,
+
+    /// Connect to ADDRESS as a Unix domain socket, even if it looks like HOST:PORT
+    #[arg(long = "unix")]
+    force_unix: bool,
+
+    /// Connect to ADDRESS instead, e.g. HOST:PORT
+    ///
+    /// Takes precedence over the positional ADDRESS and FCGI_ADDRESS. The
+    /// parameters derived from the URL, like HTTP_HOST and SERVER_NAME, are
+    /// not affected. Useful to test a staging backend with a production URL.
+    #[arg(long = "connect-to", value_name = "ADDRESS")]
+    connect_to: Option<String>,
+
+    /**
+        URL to be accessed
+
+        It is bluntly assumed that this URL is served by the FastCGI server at ADDRESS.
+        The scheme, hostname and path are passed on to the FastCGI server as appropriate.
+
+        If multiple URLs are given, a request is sent for each, and each
+        response is written to a file named after its URL, as with -O.
+     */
+    #[arg(value_name = "URL")]
+    urls: Vec<Url>,
+
+    /// Read additional URLs from FILE, one per line
+    ///
+    /// Empty lines and lines starting with '#' are ignored.
+    #[arg(long = "url-file", value_name = "FILE")]
+    url_file: Option<PathBuf>,
+
+    /// Request a URL for every number in --range, substituted for {}
+    ///
+    /// Use {:0N} to pad the number with zeros to N digits, e.g.
+    /// "http://localhost/item/{:03}".
+    #[arg(long = "url-template", value_name = "TEMPLATE", value_parser = parse_url_template, requires = "range")]
+    url_template: Option<UrlTemplate>,
+
+    /// Numbers to substitute in --url-template, e.g. "1-100"
+    #[arg(long = "range", value_name = "START-END", value_parser = parse_range, requires = "url_template")]
+    range: Option<RangeInclusive<u64>>,
+
+    /// Maximum number of requests to perform at the same time, when
+    /// requesting multiple URLs
+    #[arg(
+        long = "parallel",
+        value_name = "NUM",
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    parallel: u32,
+
+    /// Stop at the first failed request, when requesting multiple URLs
+    ///
+    /// Requests still in progress or waiting are cancelled. Without this
+    /// option, all requests are performed, and failures reported at the end.
+    #[arg(long = "fail-early")]
+    fail_early: bool,
+
+    /// Send the requests for multiple URLs over a single connection
+    ///
+    /// Requires the server to support multiplexing, which is checked using
+    /// FCGI_GET_VALUES. If it does not, a connection per request is used.
+    #[arg(long = "reuse-connection")]
+    reuse_connection: bool,
+
+    /// Set FCGI_KEEP_CONN, asking the application to keep the connection
+    /// open after the response
+    ///
+    /// The connection is still closed by fcgi once the response is read.
+    #[arg(long = "keepalive")]
+    keepalive: bool,
+
+    /// Send the request under request id N, instead of 1
+    ///
+    /// Useful to match a request with the server's logs, or to test how the
+    /// application handles other ids. 0 is reserved for management records.
+    #[arg(
+        long = "request-id",
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..),
+        conflicts_with = "reuse_connection"
+    )]
+    request_id: Option<u16>,
+
+    /// Follow redirects
+    ///
+    /// A response is considered a redirect if it has a 'Location' header and
+    /// either a 3xx status or no 'Status' header at all. Only the response to
+    /// the final request is output. A 303 response turns the request into a
+    /// GET request without a body. Any other redirect sends the body again,
+    /// so a body read from stdin is kept in memory.
+    #[arg(short = 'L', long = "location")]
+    follow_location: bool,
+
+    /// Maximum number of redirects to follow
+    #[arg(long = "max-redirs", value_name = "NUM", default_value = "50", requires = "follow_location")]
+    max_redirs: u32,
+
+    /// Query the server for FCGI_MAX_CONNS, FCGI_MAX_REQS and FCGI_MPXS_CONNS
+    /// instead of sending a request
+    ///
+    /// Not all servers respond to such management records. If no response is
+    /// received in time (see --max-time, default 2 seconds), this fails.
+    #[arg(long = "server-info")]
+    server_info: bool,
+
+    /// Print a summary of the request instead of sending it
+    ///
+    /// The parameters, request body and output files are determined as
+    /// usual, and printed to stdout along with the address, but no
+    /// connection is made.
+    #[arg(long = "dry-run", conflicts_with = "server_info")]
+    dry_run: bool,
+
+    /// URL of the request being performed
+    #[arg(skip)]
+    url: Option<Url>,
+
+    /// Act as a CGI program, forwarding the request it was invoked for
+    ///
+    /// Parameters are taken from the environment only, subject to the usual
+    /// filtering (see -e, -E). Besides the CGI/1.1 meta-variables, variables
+    /// that web servers commonly add are passed, like SCRIPT_FILENAME,
+    /// DOCUMENT_ROOT, REQUEST_URI, HTTPS and REDIRECT_STATUS. Anything else,
+    /// like PATH, must be whitelisted. The request body is read from stdin,
+    /// up to CONTENT_LENGTH bytes. The response is written to stdout including its
+    /// headers, as a CGI response.
+    #[arg(
+        long = "cgi",
+        conflicts_with_all = [
+            "urls",
+            "url_file",
+            "url_template",
+            "grp_data",
+            "data_raw",
+            "data_urlencode",
+            "query",
+            "form_parts",
+            "server_document_root",
+            "script_filename",
+            "path_info",
+            "request_uri",
+            "server_protocol",
+            "http_version",
+            "script_name",
+            "split_path_info",
+            "remote_addr",
+            "continue_at",
+            "server_addr",
+            "ssl_protocol",
+            "ssl_cipher",
+            "ssl_client_verify",
+            "credentials",
+            "as_user",
+            "request_headers",
+            "cookie",
+            "php"
+        ]
+    )]
+    cgi: bool,
+
+    /// Send given string as request body
+    ///
+    /// If given more than once, the strings are joined with '&'. Sets
+    /// CONTENT_TYPE to application/x-www-form-urlencoded, unless given
+    /// otherwise.
+    ///
+    /// Like cURL, a value starting with '@' names a file to read the string
+    /// from, or "@-" to read it from stdin. Carriage returns and newlines are
+    /// removed from the contents. Use --data-file to send a file as is, or
+    /// --data-raw to send a string starting with '@'.
+    #[arg(long = "data", group = "grp_data")]
+    data: Vec<String>,
+
+    /// Send given string as request body, like --data, but without special
+    /// meaning for '@'
+    ///
+    /// Strings given with --data-raw are joined with '&' after those given
+    /// with --data.
+    #[arg(
+        long = "data-raw",
+        value_name = "DATA",
+        conflicts_with_all = ["data_env", "form_parts", "data_from_stdin", "data_file"]
+    )]
+    data_raw: Vec<String>,
+
+    /// Send the value of environment variable VAR as request body
+    ///
+    /// Unlike --data, this keeps the body out of the process list and shell
+    /// history, e.g. for secrets.
+    #[arg(long = "data-env", value_name = "VAR", value_parser = parse_data_env, group = "grp_data")]
+    data_env: Option<String>,
+
+    /// Send URL-encoded form field
+    ///
+    /// VALUE is percent-encoded, NAME is not. Multiple fields are joined with
+    /// '&'. The fields are sent as an application/x-www-form-urlencoded body,
+    /// or in the query string for GET and HEAD requests.
+    #[arg(
+        long = "data-urlencode",
+        value_name = "[NAME=]VALUE",
+        conflicts_with_all = ["data_from_stdin", "data_file"]
+    )]
+    data_urlencode: Vec<String>,
+
+    /// Add a field to the query string, percent-encoding NAME and VALUE
+    ///
+    /// Fields are appended to any query string in the URL, e.g.
+    /// --query "q=a b" turns "/search?lang=en" into "/search?lang=en&q=a%20b".
+    #[arg(long = "query", value_name = "[NAME=]VALUE")]
+    query: Vec<String>,
+
+    /// Add multipart/form-data field, e.g. "name=value" or "name=@path"
+    ///
+    /// Prefix a value with '@' to upload the contents of a file. Append
+    /// ";type=TYPE" and/or ";filename=NAME" to override the part's content
+    /// type and file name. Implies -X POST, unless given explicitly.
+    #[arg(
+        short = 'F',
+        long = "form",
+        value_name = "NAME=VALUE",
+        value_parser = parse_form_part,
+        group = "grp_data",
+        conflicts_with = "data_urlencode"
+    )]
+    form_parts: Vec<FormPart>,
+
+    /// Read the request body from stdin into memory before sending
+    ///
+    /// This allows CONTENT_LENGTH to be set when stdin is a pipe, at the cost
+    /// of holding the entire body in memory. When stdin is redirected from a
+    /// regular file, its size is used without buffering.
+    #[arg(long = "data-from-stdin", group = "grp_data")]
+    data_from_stdin: bool,
+
+    /// Do not set CONTENT_LENGTH, and stream the body until it ends
+    ///
+    /// For methods other than GET and HEAD, the body is read from stdin
+    /// unless given otherwise, even if stdin is a pipe. The end of the body
+    /// is only signalled by the end of the FCGI_STDIN stream, which the
+    /// FastCGI spec allows, but many applications do not accept. PHP-FPM,
+    /// for one, ignores a body without CONTENT_LENGTH.
+    #[arg(long = "no-content-length", conflicts_with_all = ["data_from_stdin", "cgi"])]
+    no_content_length: bool,
+
+    /// Send contents of FILE as request body
+    ///
+    /// Use "-" to read the body from stdin. Unless stdin is redirected from a
+    /// regular file, the body is then buffered in memory to determine its
+    /// length.
+    #[arg(long = "data-file", value_name = "FILE", group = "grp_data")]
+    data_file: Option<PathBuf>,
+
+    /// Do not guess CONTENT_TYPE from the extension of the --data-file
+    ///
+    /// By default, e.g. a .json file is sent as application/json, unless
+    /// CONTENT_TYPE is given otherwise.
+    #[arg(long = "no-content-type-guess")]
+    no_content_type_guess: bool,
+
+    /// Compress the request body before sending it
+    ///
+    /// Sets HTTP_CONTENT_ENCODING and replaces CONTENT_LENGTH with the length
+    /// of the compressed body. To determine that length, both the body and
+    /// its compressed form are held in memory. An empty body is sent as is.
+    #[arg(long = "compress-request", value_name = "CODING", value_enum)]
+    compress_request: Option<ContentCoding>,
+
+    /// Set the document root
+    ///
+    /// PATH should be a valid absolute path at the server. A trailing slash
+    /// is ignored.
+    #[arg(long = "root", value_name = "PATH")]
+    server_document_root: Option<String>,
+
+    /// Set the SCRIPT_FILENAME parameter
+    ///
+    /// By default, SCRIPT_FILENAME is derived from --root and SCRIPT_NAME.
+    #[arg(long = "script-filename", value_name = "PATH")]
+    script_filename: Option<String>,
+
+    /// Set the PATH_INFO parameter
+    ///
+    /// By default, PATH_INFO is derived from the URL path and SCRIPT_NAME.
+    /// PATH_TRANSLATED is still derived from --root, if given.
+    #[arg(long = "path-info", value_name = "VALUE")]
+    path_info: Option<String>,
+
+    /// Set the REQUEST_URI parameter
+    ///
+    /// By default, REQUEST_URI is derived from the URL path and query string.
+    /// Use this to pass the original URI of a request the front-end
+    /// rewrote. PATH_INFO and QUERY_STRING are still derived from the URL.
+    #[arg(long = "request-uri", value_name = "URI")]
+    request_uri: Option<String>,
+
+    /// HTTP version of the request, which sets SERVER_PROTOCOL [default: 1.1]
+    ///
+    /// With 1.0, HTTP_HOST is not derived from the URL, as an HTTP/1.0
+    /// request need not have a Host header. It is still sent if given with
+    /// -H or -p.
+    #[arg(long = "http-version", value_name = "VERSION", value_enum)]
+    http_version: Option<HttpVersion>,
+
+    /// Set the SERVER_PROTOCOL parameter to an arbitrary value
+    ///
+    /// Prefer --http-version for HTTP versions.
+    #[arg(long = "protocol", value_name = "PROTOCOL", conflicts_with = "http_version")]
+    server_protocol: Option<String>,
+
+    /// Set the SCRIPT_NAME parameter
+    ///
+    /// If it is a prefix of the URL path, the rest of the path is passed as
+    /// PATH_INFO.
+    #[arg(long = "script")]
+    script_name: Option<String>,
+
+    /// Split the URL path into SCRIPT_NAME and PATH_INFO using REGEX
+    ///
+    /// Like nginx's fastcgi_split_path_info, the first capture group is taken
+    /// as SCRIPT_NAME and the second as PATH_INFO, e.g. with
+    /// "^(.+\.php)(/.*)$". The path is matched after percent-decoding. If it
+    /// does not match, the path is handled as without this option.
+    #[arg(
+        long = "split-path-info",
+        value_name = "REGEX",
+        value_parser = parse_split_path_info,
+        conflicts_with_all = ["script_name", "path_info"]
+    )]
+    split_path_info: Option<Regex>,
+
+    /// Set the REMOTE_ADDR parameter [default: 127.0.0.1]
+    #[arg(long = "remote-addr", value_name = "IP")]
+    remote_addr: Option<IpAddr>,
+
+    /// Set the SERVER_ADDR parameter
+    #[arg(long = "server-addr", value_name = "IP")]
+    server_addr: Option<IpAddr>,
+
+    /// Set the SSL_PROTOCOL parameter, e.g. "TLSv1.3", and HTTPS=on
+    #[arg(long = "ssl-protocol", value_name = "PROTOCOL")]
+    ssl_protocol: Option<String>,
+
+    /// Set the SSL_CIPHER parameter, e.g. "TLS_AES_256_GCM_SHA384", and
+    /// HTTPS=on
+    #[arg(long = "ssl-cipher", value_name = "CIPHER")]
+    ssl_cipher: Option<String>,
+
+    /// Set the SSL_CLIENT_VERIFY parameter, e.g. "SUCCESS" or "NONE", and
+    /// HTTPS=on
+    #[arg(long = "ssl-client-verify", value_name = "RESULT")]
+    ssl_client_verify: Option<String>,
+
+    /// Authenticate as USER using HTTP Basic authentication
+    ///
+    /// Sets AUTH_TYPE and REMOTE_USER, and sends the corresponding
+    /// Authorization header. If PASSWORD is omitted, it is prompted for.
+    #[arg(short = 'u', long = "user", value_name = "USER[:PASSWORD]", value_parser = parse_credentials)]
+    credentials: Option<Credentials>,
+
+    /// Pass NAME as the authenticated user, as a front-end that did the
+    /// authentication would
+    ///
+    /// Sets REMOTE_USER, and AUTH_TYPE to "Basic" unless given otherwise.
+    /// Unlike -u, this sends no Authorization header.
+    #[arg(long = "as-user", value_name = "NAME", conflicts_with = "credentials")]
+    as_user: Option<String>,
+
+    /// Send request header, e.g. "Accept: text/html"
+    ///
+    /// The header is passed on as the corresponding HTTP_* parameter, except
+    /// for Content-Type and Content-Length, which are passed as CONTENT_TYPE
+    /// and CONTENT_LENGTH.
+    #[arg(short = 'H', long = "header", value_name = "HEADER", value_parser = parse_request_header)]
+    request_headers: Vec<(String, String)>,
+
+    /// Send cookies, given as "NAME=VALUE[; NAME=VALUE]" or read from FILE
+    ///
+    /// FILE is a Netscape-format cookie jar, as written by -c, --cookie-jar.
+    /// Only cookies applying to the URL are sent. A missing file is ignored,
+    /// so the same file can be given to -c, --cookie-jar on the first run.
+    #[arg(short = 'b', long = "cookie", value_name = "DATA|FILE")]
+    cookie: Option<String>,
+
+    /// Save cookies to FILE after the request, in Netscape format
+    ///
+    /// The cookies set by the response are written along with those read
+    /// from a file given to -b, --cookie. When following redirects, the
+    /// cookies set by each response are sent with the next request, and
+    /// FILE is written once, after the last one. This option requires the
+    /// headers to be parsed.
+    #[arg(short = 'c', long = "cookie-jar", value_name = "FILE")]
+    cookie_jar: Option<PathBuf>,
+
+    /// Cookies read from a file given to -b, --cookie, updated with those set
+    /// by the responses to earlier requests of a redirect chain
+    #[arg(skip)]
+    cookies: Vec<Cookie>,
+
+    /// Request body read from stdin, kept to send it again when following a
+    /// redirect
+    #[arg(skip)]
+    stdin_body: Arc<OnceLock<Vec<u8>>>,
+
+    /// Set FastCGI parameter KEY to VALUE
+    ///
+    /// Parameters set this way take precedence over any derived from other
+    /// options or the environment.
+    #[arg(long = "param", value_name = "KEY=VALUE", value_parser = parse_param)]
+    params: Vec<(String, String)>,
+
+    /// Set REDIRECT_STATUS=200, as required by PHP-FPM by default
+    ///
+    /// This is done automatically if SCRIPT_FILENAME ends in ".php". A value
+    /// given via --param or the environment is left untouched.
+    #[arg(long = "php")]
+    php: bool,
+
+    /// Send environment variable VAR as FastCGI parameter
+    ///
+    /// Use SRC=DST to send the value of environment variable SRC as parameter
+    /// DST instead, e.g. "HOME=FCGI_HOME". Variables that are not set are
+    /// skipped.
+    ///
+    /// A trailing '*' in VAR matches any suffix, so "MYAPP_*" sends all
+    /// variables starting with "MYAPP_". No other wildcards are supported,
+    /// and such a prefix cannot be renamed.
+    #[arg(short = 'e', long = "pass-env", value_name = "VAR|SRC=DST", value_parser = parse_env_var)]
+    env_vars: Vec<(String, String)>,
+
+    /// Pass only excplicitly whitelisted environment variables
+    ///
+    /// Use -e, --pass-env to whitelist an environment variable
+    #[arg(long = "no-env")]
+    env_clear: bool,
+
+    /// Pass all environment variables unmodified
+    ///
+    /// Default behavior is to pass only CGI-defined metavariables and protocol variables.
+    #[arg(short = 'E', long = "full-env", conflicts_with = "env_clear")]
+    env_full: bool,
+
+    /// Send the variables defined in a dotenv-style FILE as FastCGI parameters
+    ///
+    /// Each line has the form KEY=VALUE. Blank lines and comments starting
+    /// with '#' are ignored, and values may be quoted. The variables are
+    /// always sent, regardless of --no-env, and override the environment.
+    #[arg(long = "env-file", value_name = "FILE")]
+    env_files: Vec<PathBuf>,
+
+    /// Dump response headers to file
+    ///
+    /// Use "-" to write the headers to stdout, before the body.
+    /// This option requires the headers to be parsed, in order to split the
+    /// headers from the body.
+    /// When dealing with malformed headers, refer to -i, --include.
+    #[arg(short = 'D', long = "dump-header", value_name = "FILE")]
+    response_headers_dump_file: Option<PathBuf>,
+
+    /// Format of the headers written by -D, --dump-header
+    ///
+    /// "json" writes an array of name/value objects in order of appearance,
+    /// so repeated fields like Set-Cookie are preserved.
+    #[arg(
+        long = "dump-header-format",
+        value_name = "FORMAT",
+        value_enum,
+        default_value_t,
+        requires = "response_headers_dump_file"
+    )]
+    dump_header_format: HeaderDumpFormat,
+
+    /// Include response headers in output
+    ///
+    /// The 'Status' header is left out, as it is meant for the gateway only.
+    /// With --keep-status-header, and unless required by other options,
+    /// header parsing is disabled. Thus, this allows you to dump malformed
+    /// headers.
+    ///
+    /// With -o, --output, the file holds the headers, an empty line and the
+    /// body, like a raw HTTP message. Add --nph to start it with a status
+    /// line, e.g. to save a response as a replayable fixture.
+    #[arg(short = 'i', long = "include")]
+    response_headers_include: bool,
+
+    /// Keep the 'Status' header in included headers
+    ///
+    /// It is always kept with --cgi, where the web server relies on it.
+    #[arg(long = "keep-status-header")]
+    keep_status_header: bool,
+
+    /// Decompress the response body if it is gzip or deflate encoded
+    ///
+    /// The Content-Encoding and Content-Length headers are left out of the
+    /// output when the body is decompressed, but not of the dump file.
+    /// This option requires the headers to be parsed. It is implied, unless
+    /// --no-accept-encoding, --cgi or -C, --continue-at is given.
+    #[arg(long = "decompress")]
+    decompress: bool,
+
+    /// Do not set HTTP_ACCEPT_ENCODING to "gzip, deflate"
+    ///
+    /// By default, compressed responses are accepted and decompressed, like a
+    /// browser does, unless resuming with -C, --continue-at. Use this for
+    /// applications that misbehave when asked for a compressed response. A
+    /// value given with -H is sent regardless.
+    #[arg(long = "no-accept-encoding")]
+    no_accept_encoding: bool,
+
+    /// Output the response as a non-parsed-header (NPH) response
+    ///
+    /// The 'Status' header is replaced by an HTTP status line, using the
+    /// protocol from SERVER_PROTOCOL, and all header lines end in CRLF, as
+    /// HTTP requires. Implies -i, --include.
+    #[arg(long = "nph")]
+    nph: bool,
+
+    /// Accept response header fields folded over multiple lines
+    ///
+    /// A line starting with a space or tab then continues the previous
+    /// field. This obsolete syntax is rejected by default.
+    #[arg(long = "allow-obs-fold")]
+    allow_obs_fold: bool,
+
+    /// Fail if the response header block exceeds BYTES
+    ///
+    /// Protects against a broken application sending a header that never
+    /// ends. The suffixes K, M and G multiply by 1024, 1024² and 1024³.
+    #[arg(long = "max-header-size", value_name = "BYTES", value_parser = parse_size, default_value = "64K")]
+    max_header_size: u64,
+
+    /// Character set to decode response header values with
+    ///
+    /// Invalid UTF-8 sequences are replaced, rather than causing an error.
+    #[arg(long = "header-charset", value_enum, default_value = "latin1")]
+    header_charset: HeaderCharset,
+
+    /// Fail and ignore the response body if the 'Status' header contains a value >= 400
+    #[arg(short = 'f', long = "fail")]
+    response_status_fail_on_gte_400: bool,
+
+    /// Like -f, --fail, but still output the response body
+    #[arg(long = "fail-with-body", conflicts_with = "response_status_fail_on_gte_400")]
+    response_status_fail_with_body: bool,
+
+    /// Fail if the 'Status' header contains a value >= CODE
+    ///
+    /// Implies -f, --fail unless --fail-with-body is given.
+    #[arg(long = "fail-with", value_name = "CODE")]
+    response_status_fail_threshold: Option<u16>,
+
+    /// Set exit code according to the 'Status' header
+    ///
+    /// A 2xx status results in exit code 0. 3xx, 4xx and 5xx result in exit
+    /// codes 3, 4 and 5 respectively. Any other status results in exit code 1.
+    #[arg(long = "exit-code-from-status")]
+    exit_code_from_status: bool,
+
+    /// Use the application status reported by the server as exit code
+    ///
+    /// This is the appStatus of the FCGI_END_REQUEST record, which is
+    /// clamped to 255.
+    #[arg(long = "appstatus-exit", conflicts_with = "exit_code_from_status")]
+    appstatus_exit: bool,
+
+    /// Write information about the request to stderr after completion
+    ///
+    /// Variables of the form %{name} are expanded. Available are time_connect,
+    /// time_starttransfer, time_total (in seconds), response_code, size_header,
+    /// size_download (in bytes) and num_redirects. Use %{stdout} or %{stderr}
+    /// to switch the stream to write to.
+    #[arg(short = 'w', long = "write-out", value_name = "FORMAT")]
+    write_out: Option<String>,
+
+    /// Format of the output to stdout
+    ///
+    /// With "json", a JSON object describing the response is written after
+    /// completion, including its status, headers, lengths and timings. The
+    /// body is included base64-encoded, unless written to a file with
+    /// -o, --output or -O, --remote-name.
+    #[arg(
+        long = "output-format",
+        value_name = "FORMAT",
+        value_enum,
+        default_value = "body",
+        conflicts_with_all = ["response_headers_include", "nph", "cgi"]
+    )]
+    output_format: OutputFormat,
+
+    /// Write ouput files to DIR
+    #[arg(long = "output-dir", value_name = "DIR")]
+    output_directory: Option<PathBuf>,
+
+    /// Create missing directories for output files
+    #[arg(long = "create-dirs")]
+    create_dirs: bool,
+
+    /// Do not replace existing output files
+    ///
+    /// Instead, ".1", ".2", etc. is appended to the file name until it is
+    /// unique, e.g. when multiple URLs passed to -O, --remote-name end in the
+    /// same file name.
+    #[arg(long = "no-clobber", overrides_with = "clobber")]
+    no_clobber: bool,
+
+    /// Replace existing output files, undoing an earlier --no-clobber, e.g.
+    /// from the config file
+    #[arg(long = "clobber", overrides_with = "no_clobber")]
+    clobber: bool,
+
+    /// Send output to specified file
+    #[arg(short = 'o', long = "output", value_name = "FILE", conflicts_with = "output_file_remote_name")]
+    output_file_name: Option<PathBuf>,
+
+    /// Use the final segment of the URL path as output filename
+    ///
+    /// The segment is percent-decoded, and directory separators are removed
+    /// from it, so the file is always written to the output directory.
+    #[arg(short = 'O', long = "remote-name", requires = "urls")]
+    output_file_remote_name: bool,
+
+    /// With -O, --remote-name, write to index.html if the URL path ends in a slash
+    #[arg(long = "default-filename", requires = "output_file_remote_name")]
+    default_file_name: bool,
+
+    /// Resume a download OFFSET bytes into the output file
+    ///
+    /// Requests the rest of the file with a Range header, and appends it to
+    /// the output file instead of replacing it. Use "-" to resume at the
+    /// current size of the output file. Fails unless the server responds with
+    /// 206 Partial Content. Requires -o, --output or -O, --remote-name.
+    /// Headers cannot be included, as they would end up within the body.
+    #[arg(
+        short = 'C',
+        long = "continue-at",
+        value_name = "OFFSET",
+        value_parser = parse_continue_at,
+        conflicts_with_all = ["response_headers_include", "nph"]
+    )]
+    continue_at: Option<ContinueAt>,
+
+    /// Do not write the response to stdout
+    ///
+    /// The response is still written to a file given by -o, --output or
+    /// -O, --remote-name, and -D, --dump-header and -f, --fail still apply.
+    /// Useful for checking whether a backend is up using only the exit code.
+    #[arg(short = 's', long = "silent")]
+    silent: bool,
+
+    /// Flush the output after each chunk of the response received
+    ///
+    /// Makes output of a streaming response, like server-sent events, appear
+    /// as it arrives, at the cost of throughput.
+    #[arg(short = 'N', long = "no-buffer")]
+    no_buffer: bool,
+
+    /// Fail on questionable input that would otherwise only cause a warning
+    ///
+    /// Currently this covers a --script that is not a prefix of the URL path.
+    #[arg(long = "strict")]
+    strict: bool,
+
+    /// Report the number of bytes uploaded and downloaded on stderr
+    ///
+    /// Percentages are shown when the length of the body is known.
+    #[arg(long = "progress")]
+    progress: bool,
+
+    /// Send output received on the FCGI_STDERR stream to specified file.
+    ///
+    /// Output is written as it arrives. Use "stdout" to merge it into the
+    /// output, interleaved with the response body, or "-" for stderr, which
+    /// is the default. To write to a file named "stdout", use "./stdout".
+    /// Error output generated locally will still be written to actual stderr.
+    #[arg(long = "stderr", value_name = "FILE")]
+    stderr_file_name: Option<PathBuf>,
+
+    /// Prepend STR to every line of output on FCGI_STDERR, e.g. "[app] "
+    ///
+    /// This tells it apart from error output generated locally.
+    #[arg(long = "stderr-prefix", value_name = "STR")]
+    stderr_prefix: Option<String>,
+
+    /// Fail if the application writes anything to FCGI_STDERR
+    ///
+    /// The output is still forwarded first. Useful in CI, to turn warnings
+    /// and notices logged by the application into failures.
+    #[arg(long = "fail-on-stderr")]
+    fail_on_stderr: bool,
+
+    /// Maximum time in seconds allowed for connecting to the server
+    #[arg(long = "connect-timeout", value_name = "SECONDS", value_parser = parse_seconds)]
+    connect_timeout: Option<Duration>,
+
+    /// Bind the local end of a TCP connection to IP before connecting
+    ///
+    /// Useful on multi-homed hosts, e.g. to satisfy IP-based access control
+    /// at the server. Only an IP address is accepted, not an interface name.
+    #[arg(long = "interface", value_name = "IP")]
+    interface: Option<IpAddr>,
+
+    /// Maximum time in seconds allowed for the request, once connected
+    #[arg(long = "max-time", value_name = "SECONDS", value_parser = parse_seconds)]
+    max_time: Option<Duration>,
+
+    /// Abort if the response body exceeds BYTES
+    ///
+    /// The suffixes K, M and G multiply by 1024, 1024² and 1024³. A partially
+    /// written output file is removed.
+    #[arg(long = "max-filesize", value_name = "BYTES", value_parser = parse_size)]
+    max_filesize: Option<u64>,
+
+    /// Fail if the length of the response body differs from its
+    /// Content-Length header
+    ///
+    /// A mismatch is only warned about by default. It may mean the response
+    /// was truncated by an application that crashed.
+    #[arg(long = "strict-content-length")]
+    strict_content_length: bool,
+
+    /// Retry connecting up to NUM times if the server is unavailable
+    #[arg(long = "retry", value_name = "NUM", default_value_t = 0)]
+    retry: u32,
+
+    /// Wait MS milliseconds before the first retry, doubling for each next one
+    #[arg(long = "retry-delay", value_name = "MS", default_value = "1000", value_parser = parse_millis)]
+    retry_delay: Duration,
+
+    /// Also retry if the request fails after connecting
+    ///
+    /// A request body read from stdin is not sent again on retry.
+    #[arg(long = "retry-all-errors", requires = "retry")]
+    retry_all_errors: bool,
+
+    /// Print the FastCGI parameters sent and the address connected to
+    ///
+    /// Specify twice to also print the raw header of each FastCGI record sent
+    /// and received. All verbose output goes to stderr.
+    #[arg(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Log every FastCGI record sent and received to FILE
+    ///
+    /// Each record is logged with its type, request id and length, followed
+    /// by a hex and ASCII dump of its content. Use "-" to log to stderr.
+    #[arg(long = "trace", value_name = "FILE")]
+    trace_file: Option<PathBuf>,
+
+    /// When to colorize verbose and trace output
+    ///
+    /// "auto" colorizes output to a terminal, unless the NO_COLOR environment
+    /// variable is set to a non-empty value.
+    #[arg(long = "color", value_name = "WHEN", value_enum, default_value_t)]
+    color: ColorChoice,
+
+    /// Role of the FastCGI application
+    ///
+    /// In the authorizer role, no body is sent, nor are CONTENT_LENGTH,
+    /// PATH_INFO, PATH_TRANSLATED and SCRIPT_NAME.
+    #[arg(long = "role", value_enum, default_value = "responder")]
+    role: Role,
+
+    /// Send contents of FILE on the FCGI_DATA stream
+    ///
+    /// Requires --role filter. FCGI_DATA_LENGTH and FCGI_DATA_LAST_MOD are
+    /// set from the file's metadata.
+    #[arg(long = "filter-data", value_name = "FILE")]
+    filter_data_file: Option<PathBuf>,
+
+    /// Set FastCGI parameter REQUEST_METHOD
+    ///
+    /// METHOD is converted to uppercase. For GET and HEAD, no body is sent
+    /// unless given explicitly, e.g. with --data. For other methods, the body
+    /// is read from stdin if it is not given otherwise, provided its length
+    /// can be determined (see --data-from-stdin).
+    #[arg(
+        short = 'X',
+        long = "request",
+        value_name = "METHOD",
+        value_parser = parse_method,
+        default_value = "GET",
+        default_value_if("form_parts", ArgPredicate::IsPresent, Some("POST"))
+    )]
+    request_method: String,
+
+    /// Allow a request method other than the standard HTTP and WebDAV ones
+    #[arg(long = "allow-custom-method")]
+    allow_custom_method: bool,
+
+    /// Send a HEAD request, and output only the response headers
+    ///
+    /// Overrides -X, --request. Any body sent by the server is discarded.
+    #[arg(short = 'I', long = "head")]
+    head: bool,
+
+    /// Read default options from FILE [default: ./fcgi-cli.toml, if it exists]
+    ///
+    /// FILE is a TOML file with long option names as keys, e.g.
+    /// root = "/var/www/html" or pass-env = ["APP_ENV"]. Options given on the
+    /// command line take precedence; repeatable ones add to those in FILE.
+    #[arg(long = "config", value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Do not read ./fcgi-cli.toml
+    #[arg(long = "no-config", conflicts_with = "config")]
+    no_config: bool,
+
+    /// Print a completion script for SHELL and exit
+    #[arg(long = "generate-completions", value_name = "SHELL", hide = true)]
+    generate_completions: Option<Shell>,
+}
+
+impl Cli {
+    /// Parse the command line, preceded by the options from the config file
+    ///
+    /// Like `Cli::parse`, this exits on invalid arguments.
+    pub fn parse_with_config() -> Result<Self> {
+        let mut args: Vec<OsString> = env::args_os().collect();
+
+        let mut config = None;
+        let mut no_config = false;
+        let mut iter = args.iter().skip(1).take_while(|a| { *a != "--" });
+        while let Some(arg) = iter.next() {
+            let arg = arg.to_string_lossy();
+            if arg == "--no-config" {
+                no_config = true;
+            } else if arg == "--config" {
+                config = iter.next().map(PathBuf::from);
+            } else if let Some(path) = arg.strip_prefix("--config=") {
+                config = Some(PathBuf::from(path));
+            }
+        }
+
+        let path = match config {
+            Some(path) if !no_config => Some(path),
+            None if !no_config => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|p| { p.is_file() }),
+            _ => None,
+        };
+        if let Some(path) = path {
+            let config_args = config_args(&Cli::command(), &path).map_err(|e| { anyhow!(e) })?;
+            args.splice(1..1, config_args);
+        }
+
+        Ok(Cli::parse_from(args))
+    }
+
+    fn is_envvar_whitelisted(&self, var_name: &str) -> bool {
+        if self.env_full {
+            return true;
+        }
+
+        if !self.env_clear
+            && (var_name.starts_with("HTTP_")
+                || CGI_META_VARS.contains(&var_name)
+                || (self.cgi && CGI_BRIDGE_VARS.contains(&var_name)))
+        {
+            return true;
+        }
+
+        self.env_vars.iter().any(|(src, dst)| { src == dst && env_var_matches(src, var_name) })
+    }
+
+    /// Names of the parameters environment variable `var_name` is passed as
+    ///
+    /// A variable renamed with -e SRC=DST is passed under its own name only
+    /// if it is also whitelisted as such.
+    fn env_param_names(&self, var_name: &str) -> Vec<String> {
+        let mut names: Vec<String> = self.env_vars
+            .iter()
+            .filter(|(src, dst)| { src == var_name && dst != var_name })
+            .map(|(_, dst)| { dst.clone() })
+            .collect();
+        if self.is_envvar_whitelisted(var_name) {
+            names.push(var_name.to_string());
+        }
+        names
+    }
+
+    /// Effective request method
+    fn method(&self) -> &str {
+        if self.head {
+            "HEAD"
+        } else {
+            &self.request_method
+        }
+    }
+
+    /// Fail on an unknown request method, unless --allow-custom-method
+    fn check_method(&self) -> Result<()> {
+        if !self.allow_custom_method && !KNOWN_METHODS.contains(&self.method()) {
+            bail!("Unknown request method \"{}\". Use --allow-custom-method to send it anyway.", self.method());
+        }
+        Ok(())
+    }
+
+    /// Fail on options that do not apply to the role
+    fn check_role(&self) -> Result<()> {
+        if self.filter_data_file.is_some() && self.role != Role::Filter {
+            bail!("--filter-data requires --role filter");
+        }
+        Ok(())
+    }
+
+    fn is_get_like(&self) -> bool {
+        matches!(self.method(), "GET" | "HEAD")
+    }
+
+    /// Form fields given with --data-urlencode, encoded and joined
+    fn urlencoded_form(&self) -> Option<String> {
+        if self.data_urlencode.is_empty() {
+            return None;
+        }
+
+        let fields: Vec<String> = self.data_urlencode.iter().map(|f| { encode_field(f) }).collect();
+        Some(fields.join("&"))
+    }
+
+    /// Fields to add to the query string of the URL: those given with --query,
+    /// and for GET and HEAD requests, those given with --data-urlencode
+    fn added_query(&self) -> Option<String> {
+        let mut fields: Vec<String> = self.query.iter().map(|f| { encode_query_field(f) }).collect();
+        if self.is_get_like() {
+            fields.extend(self.urlencoded_form());
+        }
+        (!fields.is_empty()).then(|| { fields.join("&") })
+    }
+
+    /// Request body given on the command line
+    ///
+    /// For GET and HEAD requests, fields given with --data-urlencode are sent
+    /// in the query string instead.
+    fn inline_data(&self) -> Option<Cow<'_, str>> {
+        let form = if self.is_get_like() { None } else { self.urlencoded_form() };
+        let data = match self.data.as_slice() {
+            [] => self.data_env.as_deref().map(Cow::Borrowed),
+            [data] => Some(Cow::Borrowed(data.as_str())),
+            data => Some(Cow::Owned(data.join("&"))),
+        };
+        match (data, form) {
+            (Some(data), Some(form)) => Some(Cow::Owned(format!("{}&{}", data, form))),
+            (data, None) => data,
+            (None, form) => form.map(Cow::Owned),
+        }
+    }
+
+    /// SCRIPT_NAME and PATH_INFO split from the URL path by --split-path-info,
+    /// if it matches
+    fn split_path(&self) -> Option<(String, String)> {
+        let (regex, url) = (self.split_path_info.as_ref()?, self.url.as_ref()?);
+        let path = percent_decode_str(url.path()).decode_utf8_lossy();
+        let captures = regex.captures(&path)?;
+        let capture = |i| { captures.get(i).map_or_else(String::new, |m| { m.as_str().to_string() }) };
+        Some((capture(1), capture(2)))
+    }
+
+    /// Document root given with --root, without trailing slash, so a script
+    /// name or path info can be appended to it
+    fn document_root(&self) -> Option<&str> {
+        self.server_document_root
+            .as_deref()
+            .map(|root| { root.strip_suffix('/').unwrap_or(root) })
+    }
+
+    fn transport(&self) -> Result<Transport, FcgiCliError> {
+        let env_address = env::var(ADDRESS_ENV_VAR).ok().filter(|a| { !a.is_empty() });
+        let address = self.connect_to.as_deref().or(self.address.as_deref()).or(env_address.as_deref());
+        let Some(address) = address else {
+            return socket_activation_transport()
+                .map_err(FcgiCliError::Transport)?
+                .ok_or_else(|| {
+                    FcgiCliError::Transport(format!(
+                        "No address given, {} is not set, and no socket was passed by systemd (LISTEN_FDS)",
+                        ADDRESS_ENV_VAR
+                    ))
+                });
+        };
+
+        let transport = if self.force_tcp {
+            tcp_transport(address)
+        } else if self.force_unix {
+            unix_transport(address)
+        } else {
+            resolve_transport(address)
+        };
+        transport.validate().map_err(FcgiCliError::Transport)?;
+        if let Some(interface) = self.interface.filter(|_| { !matches!(transport, Transport::Tcp(_)) }) {
+            return Err(FcgiCliError::Transport(format!(
+                "Cannot bind to local address {}: --interface only applies to TCP, not {}",
+                interface,
+                transport
+            )));
+        }
+        Ok(transport)
+    }
+
+    fn resolve_output_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        if let Some(output_directory) = self.output_directory.as_ref() {
+            output_directory.join(path)
+        } else {
+            path.as_ref().to_path_buf()
+        }
+    }
+
+    fn real_output_file_name(&self) -> Result<Option<PathBuf>> {
+        if !self.output_file_remote_name {
+            return Ok(self.output_file_name.clone());
+        }
+
+        let url = self.url.as_ref().unwrap(); // cli should have caught this
+        let last_path_segment = url.path_segments().and_then(|mut s| { s.next_back() }).unwrap_or_default();
+        match last_path_segment {
+            "" if self.default_file_name => Ok(Some(PathBuf::from(DEFAULT_FILE_NAME))),
+            "" => bail!(
+                "Cannot derive an output file name from {}, as its path ends in a slash. \
+                Use -o, --output to name the file, or --default-filename.",
+                url
+            ),
+            _ => match sanitize_file_name(last_path_segment) {
+                Some(file_name) => Ok(Some(PathBuf::from(file_name))),
+                None => bail!("Refusing to use \"{}\" from {} as output file name.", last_path_segment, url),
+            },
+        }
+    }
+
+    /// Check that --script is a prefix of the URL path, as it is stripped
+    /// from the path to get PATH_INFO
+    fn check_script_name(&self) -> Result<()> {
+        let (Some(script_name), Some(url), None) = (self.script_name.as_ref(), self.url.as_ref(), self.path_info.as_ref()) else {
+            return Ok(());
+        };
+
+        let path = percent_decode_str(url.path()).decode_utf8_lossy();
+        if strip_script_name(&path, script_name).is_none() {
+            let message = format!(
+                "Script name \"{}\" is not a prefix of URL path \"{}\", so PATH_INFO is the entire path",
+                script_name,
+                path
+            );
+            if self.strict {
+                bail!(message);
+            }
+            eprintln!("Warning: {}", message);
+        }
+
+        Ok(())
+    }
+
+    /// Offset to resume the download at with -C, --continue-at, if any
+    fn resume_offset(&self) -> Result<Option<u64>> {
+        let Some(continue_at) = self.continue_at else {
+            return Ok(None);
+        };
+        let Some(file_name) = self.real_output_file_name()? else {
+            bail!("Cannot resume without an output file. Use -o, --output or -O, --remote-name.");
+        };
+
+        let offset = match continue_at {
+            ContinueAt::Offset(offset) => offset,
+            ContinueAt::Auto => {
+                let path = self.resolve_output_path(file_name);
+                match std::fs::metadata(&path) {
+                    Ok(metadata) => metadata.len(),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+                    Err(e) => return Err(e).with_context(|| { format!("Failed to read size of {}", path.display()) }),
+                }
+            }
+        };
+        // Nothing to resume from, so just download the whole file
+        Ok(Some(offset).filter(|&offset| { offset > 0 }))
+    }
+
+    fn fail_threshold(&self) -> Option<u16> {
+        if self.response_status_fail_on_gte_400
+            || self.response_status_fail_with_body
+            || self.response_status_fail_threshold.is_some()
+        {
+            Some(self.response_status_fail_threshold.unwrap_or(400))
+        } else {
+            None
+        }
+    }
+
+    fn need_status(&self) -> bool {
+        self.fail_threshold().is_some() || self.exit_code_from_status || self.nph
+    }
+
+    fn include_headers(&self) -> bool {
+        // The JSON report lists the headers separately
+        (self.response_headers_include || self.cgi || self.nph || self.head)
+            && self.output_format != OutputFormat::Json
+    }
+
+    /// Whether to leave the 'Status' header out of included headers
+    fn strip_status_header(&self) -> bool {
+        self.include_headers() && !self.keep_status_header && !self.cgi
+    }
+
+    /// Styles for verbose output, which goes to stderr
+    fn colors(&self) -> Colors {
+        Colors::new(self.color, std::io::stderr().is_terminal())
+    }
+
+    fn header_parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            allow_obs_fold: self.allow_obs_fold,
+            charset: self.header_charset,
+            max_size: Some(self.max_header_size as usize),
+        }
+    }
+
+    /// Whether to ask for a compressed response
+    ///
+    /// Not when resuming a download, as part of a compressed body cannot be
+    /// decompressed on its own.
+    fn accept_encoding(&self) -> bool {
+        !self.no_accept_encoding && !self.cgi && self.continue_at.is_none()
+    }
+
+    /// Whether to decompress the response body, if it is encoded
+    fn decompress(&self) -> bool {
+        self.decompress || self.accept_encoding()
+    }
+
+    /// Whether the response cannot be handled without valid headers
+    fn require_headers(&self) -> bool {
+        self.need_status() || self.response_headers_dump_file.is_some() || self.head
+    }
+
+    fn need_parse_header(&self) -> bool {
+        self.need_status()
+            || !self.include_headers()
+            || self.response_headers_dump_file.is_some()
+            || self.decompress()
+            || self.head
+            || self.follow_location
+            || self.write_out.is_some()
+            || self.cookie_jar.is_some()
+            || self.strip_status_header()
+            || self.output_format == OutputFormat::Json
+            || self.continue_at.is_some()
+    }
+
+    /// Cookies read from the file given to -b, --cookie, if any
+    fn read_cookies(&self) -> Result<Vec<Cookie>> {
+        match self.cookie.as_deref() {
+            Some(path) if !path.contains('=') => match read_jar(Path::new(path)) {
+                Ok(jar) => Ok(jar),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+                Err(e) => Err(e).with_context(|| { format!("Failed to read cookies from {}", path) }),
+            },
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Whether cookies set by responses are kept, to be sent with the next
+    /// request of a redirect chain or saved to a file
+    fn keep_cookies(&self) -> bool {
+        self.cookie.is_some() || self.cookie_jar.is_some()
+    }
+
+    /// Value for the Cookie header, from -b, --cookie and the cookies kept
+    fn cookie_header(&self) -> Option<String> {
+        let data = self.cookie.as_deref().filter(|data| { data.contains('=') });
+        match (data, cookie_header(&self.cookies, self.url.as_ref())) {
+            (Some(data), Some(kept)) => Some(format!("{}; {}", data, kept)),
+            (data, kept) => data.map(str::to_string).or(kept),
+        }
+    }
+}
+
+trait ParamsExt<'a> {
+    fn set_from_cli(self, cli: &Cli) -> Self;
+    fn set_basic_auth(self, user: &str, password: &str) -> Self;
+    fn set_from_env<I, S1, S2>(self, vars: I) -> Self
+        where
+            I: IntoIterator<Item = (S1, S2)>,
+            S1: Into<Cow<'a, str>>,
+            S2: Into<Cow<'a, str>>;
+}
+
+impl<'a> ParamsExt<'a> for Params<'a> {
+    fn set_from_cli(mut self, cli: &Cli) -> Self {
+        self = self.request_method(cli.method().to_string());
+
+        if let Some(protocol) = cli.server_protocol.as_ref() {
+            self = self.server_protocol(protocol.clone());
+        } else if let Some(version) = cli.http_version {
+            self = self.server_protocol(version.server_protocol());
+        }
+
+        let split_path = cli.split_path();
+        let script_name =
+            if let Some(sn) = cli.script_name.as_ref() { 
+                self = self.script_name(sn.clone());
+                sn
+            } else if let Some((sn, _)) = split_path.as_ref() {
+                self = self.script_name(sn.clone());
+                sn
+            } else {
+                self.get("SCRIPT_NAME").map(|c| { c.as_ref() }).unwrap_or_default()
+            }.to_string();
+
+        if let Some(script_filename) = cli.script_filename.as_ref() {
+            self = self.script_filename(script_filename.clone());
+        } else if !script_name.is_empty() {
+            if let Some(root) = cli.document_root() {
+                self = self.script_filename(root.to_string() + script_name.as_str())
+            }
+        }
+
+        let added_query = cli.added_query();
+
+        let path_info = if let Some(path_info) = cli.path_info.as_ref() {
+            Some(path_info.clone())
+        } else if let Some((_, path_info)) = split_path {
+            Some(path_info).filter(|p| { !p.is_empty() })
+        } else {
+            cli.url.as_ref().map(|url| {
+                // Unlike REQUEST_URI, PATH_INFO is not URL-encoded
+                let p = percent_decode_str(url.path()).decode_utf8_lossy();
+                strip_script_name(&p, &script_name).unwrap_or(&p).to_string()
+            }).filter(|p| { !p.is_empty() })
+        };
+
+        if let Some(path_info) = path_info {
+            if !path_info.is_empty() {
+                if let Some(root) = cli.document_root() {
+                    self.insert("PATH_TRANSLATED".into(), (root.to_owned() + path_info.as_str()).into());
+                }
+            }
+            self.insert("PATH_INFO".into(), path_info.into());
+        }
+
+        if let Some(url) = cli.url.as_ref() {
+            // Like the Host header, include the port only if it is not the
+            // default. An IPv6 address is already enclosed in brackets.
+            if let Some(host) = url.host_str().filter(|_| { cli.http_version != Some(HttpVersion::Http10) }) {
+                let host = match url.port() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host.to_string(),
+                };
+                self.insert("HTTP_HOST".into(), host.into());
+            }
+
+            if let Some(host) = url.host_str() {
+                self = self.server_name(host.to_string());
+            }
+
+            if let Some(port) = url.port_or_known_default() {
+                self = self.server_port(port);
+            }
+
+            let query = match (url.query(), added_query.as_ref()) {
+                (Some(qs), Some(added)) => Some(format!("{}&{}", qs, added)),
+                (qs, added) => qs.map(str::to_string).or_else(|| { added.cloned() }),
+            };
+
+            if let Some(qs) = query {
+                self = self
+                    .request_uri(format!("{}?{}", url.path(), qs))
+                    .query_string(qs);
+            } else {
+                self = self.request_uri(url.path().to_string());
+            }
+
+            // An HTTPS value passed along from the environment is kept, even
+            // if it does not agree with the URL
+            if url.scheme() == "https" && self.get("HTTPS").is_none() {
+                self.insert("HTTPS".into(), "on".into());
+            }
+            self.insert("REQUEST_SCHEME".into(), url.scheme().to_string().into());
+        } else if let Some(added) = added_query {
+            self = self.query_string(added);
+        };
+
+        if let Some(request_uri) = cli.request_uri.as_ref() {
+            self = self.request_uri(request_uri.clone());
+        }
+
+        if let Some(remote_addr) = cli.remote_addr {
+            self.insert("REMOTE_ADDR".into(), remote_addr.to_string().into());
+        } else if self.get("REMOTE_ADDR").is_none() {
+            self.insert("REMOTE_ADDR".into(), DEFAULT_REMOTE_ADDR.into());
+        }
+
+        if let Some(server_addr) = cli.server_addr {
+            self.insert("SERVER_ADDR".into(), server_addr.to_string().into());
+        }
+
+        if let Some(user) = cli.as_user.as_ref() {
+            self.insert("REMOTE_USER".into(), user.clone().into());
+            if self.get("AUTH_TYPE").is_none() {
+                self.insert("AUTH_TYPE".into(), "Basic".into());
+            }
+        }
+
+        // Details of a TLS connection terminated at the front-end, which
+        // implies the request arrived over HTTPS, whatever the URL says
+        let ssl_params = [
+            ("SSL_PROTOCOL", cli.ssl_protocol.as_ref()),
+            ("SSL_CIPHER", cli.ssl_cipher.as_ref()),
+            ("SSL_CLIENT_VERIFY", cli.ssl_client_verify.as_ref()),
+        ];
+        for (name, value) in ssl_params.iter() {
+            if let Some(value) = value {
+                self.insert((*name).into(), value.to_string().into());
+            }
+        }
+        if ssl_params.iter().any(|(_, value)| { value.is_some() }) {
+            self.insert("HTTPS".into(), "on".into());
+        }
+
+        if let Some(data) = cli.inline_data() {
+            if self.get("CONTENT_LENGTH").is_none() && !cli.no_content_length {
+                self = self.content_length(data.len());
+            }
+        };
+
+        let is_form = !cli.data.is_empty() || (!cli.data_urlencode.is_empty() && !cli.is_get_like());
+        if is_form && self.get("CONTENT_TYPE").is_none() {
+            self = self.content_type("application/x-www-form-urlencoded");
+        }
+
+        let data_file = cli.data_file.as_ref().filter(|p| { p.as_os_str() != "-" });
+        if let Some(path) = data_file.filter(|_| { !cli.no_content_type_guess }) {
+            if self.get("CONTENT_TYPE").is_none() {
+                self = self.content_type(guess_content_type(path));
+            }
+        }
+
+        if let Some(cookie) = cli.cookie_header() {
+            self.insert("HTTP_COOKIE".into(), cookie.into());
+        }
+
+        if cli.accept_encoding() && self.get("HTTP_ACCEPT_ENCODING").is_none() {
+            self.insert("HTTP_ACCEPT_ENCODING".into(), DEFAULT_ACCEPT_ENCODING.into());
+        }
+
+        for (name, value) in cli.request_headers.iter() {
+            self.insert(name.clone().into(), value.clone().into());
+        }
+
+        let is_php_script = self
+            .get("SCRIPT_FILENAME")
+            .is_some_and(|f| { f.ends_with(".php") });
+        if (cli.php || is_php_script) && self.get("REDIRECT_STATUS").is_none() {
+            self.insert("REDIRECT_STATUS".into(), "200".into());
+        }
+
+        for (name, value) in cli.params.iter() {
+            self.insert(name.clone().into(), value.clone().into());
+        }
+
+        self
+    }
+
+    fn set_basic_auth(mut self, user: &str, password: &str) -> Self {
+        self.insert("AUTH_TYPE".into(), "Basic".into());
+        self.insert("REMOTE_USER".into(), user.to_string().into());
+        self.insert("HTTP_AUTHORIZATION".into(), basic_authorization(user, password).into());
+        self
+    }
+
+    fn set_from_env<I, S1, S2>(mut self, vars: I) -> Self
+        where
+            I: IntoIterator<Item = (S1, S2)>,
+            S1: Into<Cow<'a, str>>,
+            S2: Into<Cow<'a, str>>
+    {
+        self.extend(vars.into_iter().map(|t| { (t.0.into(), t.1.into()) }));
+        self
+    }
+}
+
+/// Strip `script_name` from the start of `path`, provided that it ends at a
+/// path segment boundary
+fn strip_script_name<'p>(path: &'p str, script_name: &str) -> Option<&'p str> {
+    let rest = path.strip_prefix(script_name)?;
+    (rest.is_empty() || rest.starts_with('/') || script_name.ends_with('/')).then_some(rest)
+}
+
+fn parse_split_path_info(s: &str) -> Result<Regex, String> {
+    let regex = Regex::new(s).map_err(|e| { e.to_string() })?;
+    // The implicit group for the entire match is counted too
+    if regex.captures_len() < 3 {
+        return Err("Expected two capture groups, for SCRIPT_NAME and PATH_INFO".to_string());
+    }
+    Ok(regex)
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| { Duration::try_from_secs_f64(secs).ok() })
+        .ok_or_else(|| { format!("Invalid number of seconds \"{}\"", s) })
+}
+
+fn parse_millis(s: &str) -> Result<Duration, String> {
+    s.parse::<u64>()
+        .map(Duration::from_millis)
+        .map_err(|_| { format!("Invalid number of milliseconds \"{}\"", s) })
+}
+
+/// HTTP version given to --http-version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum HttpVersion {
+    #[value(name = "1.0")]
+    Http10,
+    #[value(name = "1.1")]
+    Http11,
+    #[value(name = "2")]
+    Http2,
+}
+
+impl HttpVersion {
+    fn server_protocol(self) -> &'static str {
+        match self {
+            HttpVersion::Http10 => "HTTP/1.0",
+            HttpVersion::Http11 => "HTTP/1.1",
+            HttpVersion::Http2 => "HTTP/2.0",
+        }
+    }
+}
+
+/// Offset given to -C, --continue-at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContinueAt {
+    /// The current size of the output file
+    Auto,
+    Offset(u64),
+}
+
+fn parse_continue_at(s: &str) -> Result<ContinueAt, String> {
+    if s == "-" {
+        return Ok(ContinueAt::Auto);
+    }
+    s.parse()
+        .map(ContinueAt::Offset)
+        .map_err(|_| { format!("Invalid offset \"{}\", expected a number of bytes or \"-\"", s) })
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match s.chars().last().map(|c| { c.to_ascii_uppercase() }) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| { n.checked_mul(multiplier) })
+        .ok_or_else(|| { format!("Invalid size \"{}\"", s) })
+}
+
+fn parse_param(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| { format!("Malformed parameter \"{}\", expected \"KEY=VALUE\"", s) })?;
+
+    if !is_token(name) {
+        return Err(format!("Invalid parameter name \"{}\"", name));
+    }
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Whether environment variable `name` matches `pattern`, which is either a
+/// variable name or a prefix followed by '*'
+fn env_var_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+/// Normalize a request method to uppercase, checking that it is a token
+fn parse_method(s: &str) -> Result<String, String> {
+    if !is_token(s) {
+        return Err(format!("Invalid request method \"{}\"", s));
+    }
+    Ok(s.to_ascii_uppercase())
+}
+
+/// Read the body given by --data-env from the environment
+fn parse_data_env(name: &str) -> Result<String, String> {
+    env::var(name).map_err(|e| {
+        match e {
+            env::VarError::NotPresent => format!("Environment variable {} is not set", name),
+            env::VarError::NotUnicode(_) => format!("Environment variable {} is not valid UTF-8", name),
+        }
+    })
+}
+
+/// Parse VAR or SRC=DST, returning the variable name and the parameter name
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    let (src, dst) = s.split_once('=').unwrap_or((s, s));
+    if src.is_empty() {
+        return Err(format!("Malformed environment variable \"{}\", expected \"VAR\" or \"SRC=DST\"", s));
+    }
+    if !is_token(dst) {
+        return Err(format!("Invalid parameter name \"{}\"", dst));
+    }
+    if src.ends_with('*') && src != dst {
+        return Err(format!("Cannot rename variables matching \"{}\"", src));
+    }
+
+    Ok((src.to_string(), dst.to_string()))
+}
+
+fn parse_request_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| { format!("Malformed header \"{}\", expected \"Name: value\"", s) })?;
+
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("Invalid header name \"{}\"", name));
+    }
+
+    Ok((cgi_param_name(name), value.trim().to_string()))
+}
+
+/// Perform the request for each URL, returning the exit code on success
+///
+/// Cancelling `interrupt` stops the requests in progress, failing with
+/// `FcgiCliError::Interrupted`.
+pub async fn run(cli: Cli, interrupt: CancellationToken) -> Result<u8> {
+    if let Some(shell) = cli.generate_completions {
+        clap_complete::generate(shell, &mut Cli::command(), "fcgi", &mut std::io::stdout());
+        return Ok(0);
+    }
+
+    let mut cli = cli;
+    let mut urls = cli.urls.clone();
+    // A URL can never be an address, so ADDRESS must have been omitted
+    if let Some(url) = cli.address.as_deref().and_then(address_as_url) {
+        urls.insert(0, url);
+        cli.address = None;
+    }
+    if let Some(path) = cli.url_file.as_ref() {
+        urls.extend(read_url_file(path)?);
+    }
+    let (templated, templated_count) = match (cli.url_template.clone(), cli.range.clone()) {
+        (Some(template), Some(range)) => {
+            let count = (range.end() - range.start()).saturating_add(1);
+            (Some(expand_url_template(template, range)?), count)
+        }
+        _ => (None, 0),
+    };
+    let total = urls.len() as u64 + templated_count;
+    let mut urls = urls.into_iter().map(Ok).chain(templated.into_iter().flatten());
+
+    // Prompt for the password only once, rather than for every request
+    if let Some(credentials) = cli.credentials.as_mut() {
+        credentials.password = Some(credentials.password().map_err(|e| { anyhow!("Failed to read password: {}", e) })?);
+    }
+
+    // Read files and stdin only once, rather than for every request
+    for data in cli.data.iter_mut() {
+        if let Some(source) = data.strip_prefix('@') {
+            *data = until_interrupted(&interrupt, read_data_arg(source)).await?;
+        }
+    }
+    let data_raw = std::mem::take(&mut cli.data_raw);
+    cli.data.extend(data_raw);
+
+    // Each request appends to the trace, so start with an empty one
+    if let Some(path) = cli.trace_file.as_ref().filter(|p| { p.as_os_str() != "-" }) {
+        File::create(path).with_context(|| { format!("Failed to create trace file {}", path.display()) })?;
+    }
+
+    if interrupt.is_cancelled() {
+        return Err(FcgiCliError::Interrupted.into());
+    }
+
+    if total <= 1 {
+        let cli = Cli { url: urls.next().transpose()?, ..cli };
+        return execute(&cli, None, &interrupt).await;
+    }
+
+    if cli.output_file_name.is_some() {
+        bail!("Cannot write the responses for multiple URLs to a single file. Use -O, --remote-name instead.");
+    }
+
+    let local = LocalSet::new();
+    local.run_until(async {
+        let mux = if cli.reuse_connection && !cli.dry_run {
+            until_interrupted(&interrupt, open_multiplexer(&cli)).await?.map(Rc::new)
+        } else {
+            None
+        };
+
+        // Also cancelled by --fail-early
+        let cancel = interrupt.child_token();
+        let mut tasks = JoinSet::new();
+        let mut started = 0;
+        let mut exit_code = 0;
+        let mut failures = 0;
+        let mut cancelled = 0;
+        loop {
+            // Take the next URL only once there is room for another request,
+            // as a template may expand to more URLs than fit in memory
+            if tasks.len() < cli.parallel as usize && !cancel.is_cancelled() {
+                match urls.next() {
+                    Some(Ok(url)) => {
+                        started += 1;
+                        let cli = Cli { url: Some(url), output_file_remote_name: true, ..cli.clone() };
+                        let mux = mux.clone();
+                        let cancel = cancel.clone();
+                        tasks.spawn_local(async move {
+                            let result = execute(&cli, mux.as_deref(), &cancel).await;
+                            (cli.url, result)
+                        });
+                        continue;
+                    }
+                    Some(Err(e)) => {
+                        started += 1;
+                        eprintln!("{:#}", e);
+                        failures += 1;
+                        if cli.fail_early {
+                            cancel.cancel();
+                        }
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+
+            let Some(joined) = tasks.join_next().await else {
+                break;
+            };
+            match joined? {
+                (_, Ok(code)) => exit_code = exit_code.max(code),
+                (_, Err(e)) if matches!(e.downcast_ref(), Some(FcgiCliError::Interrupted)) => cancelled += 1,
+                (url, Err(e)) => {
+                    eprintln!("{}: {:#}", url.map_or_else(String::new, String::from), e);
+                    failures += 1;
+                    if cli.fail_early {
+                        cancel.cancel();
+                    }
+                }
+            }
+        }
+        // URLs not requested after cancelling count as cancelled as well
+        cancelled += total - started;
+
+        if interrupt.is_cancelled() {
+            return Err(FcgiCliError::Interrupted.into());
+        }
+        if cancelled > 0 {
+            bail!("{} of {} requests failed, {} cancelled", failures, total, cancelled);
+        }
+        if failures > 0 {
+            bail!("{} of {} requests failed", failures, total);
+        }
+        Ok(exit_code)
+    }).await
+}
+
+/// Run `future` to completion, unless `interrupt` is cancelled first
+async fn until_interrupted<T>(interrupt: &CancellationToken, future: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::select! {
+        biased;
+        _ = interrupt.cancelled() => Err(FcgiCliError::Interrupted.into()),
+        result = future => result,
+    }
+}
+
+/// Connect to the server, and set up the connection to be shared by all
+/// requests if the server supports multiplexing.
+async fn open_multiplexer(cli: &Cli) -> Result<Option<Multiplexer>> {
+    let transport = cli.transport()?;
+    let mut stream = connect(cli, &transport).await?;
+
+    let values = time::timeout(MANAGEMENT_TIMEOUT, get_values(&mut stream, &["FCGI_MPXS_CONNS"])).await;
+    match values {
+        Ok(Ok(values)) if supports_multiplexing(&values) => Ok(Some(Multiplexer::new(stream))),
+        Ok(Ok(_)) => {
+            eprintln!("Warning: Server does not support multiplexing. Using a connection per request.");
+            Ok(None)
+        }
+        Ok(Err(e)) => {
+            eprintln!("Warning: Failed to query server for multiplexing support: {}. Using a connection per request.", e);
+            Ok(None)
+        }
+        Err(_) => {
+            eprintln!("Warning: Server did not respond to FCGI_GET_VALUES. Using a connection per request.");
+            Ok(None)
+        }
+    }
+}
+
+/// Print the values of the variables the server reports for FCGI_GET_VALUES
+async fn query_server_info(cli: &Cli) -> Result<u8> {
+    let transport = cli.transport()?;
+    let mut stream = connect(cli, &transport).await?;
+
+    let timeout = cli.max_time.unwrap_or(MANAGEMENT_TIMEOUT);
+    let names = ["FCGI_MAX_CONNS", "FCGI_MAX_REQS", "FCGI_MPXS_CONNS"];
+    let values = time::timeout(timeout, get_values(&mut stream, &names))
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "Server {} did not respond to FCGI_GET_VALUES within {} seconds. \
+                Not all servers support management records.",
+                transport,
+                timeout.as_secs_f64()
+            )
+        })?
+        .context("Failed to query server info")?;
+
+    for name in names {
+        match values.iter().find(|(n, _)| { n == name }) {
+            Some((_, value)) => println!("{}={}", name, value),
+            None => println!("{} unknown", name),
+        }
+    }
+
+    Ok(0)
+}
+
+/// Turn a URL path segment into a file name that cannot escape the output
+/// directory
+///
+/// The segment is percent-decoded, and any directory separators it then
+/// contains are removed. Returns `None` if nothing usable remains.
+fn sanitize_file_name(segment: &str) -> Option<String> {
+    let decoded = percent_decode_str(segment).decode_utf8_lossy();
+    let file_name: String = decoded.chars().filter(|c| { !matches!(c, '/' | '\\' | '\0') }).collect();
+    match file_name.trim() {
+        "" | "." | ".." => None,
+        _ => Some(file_name),
+    }
+}
+
+/// Parse `address` as a URL, if it is one rather than an address
+fn address_as_url(address: &str) -> Option<Url> {
+    if !address.contains("://") || address.starts_with("fd://") {
+        return None;
+    }
+    Url::parse(address).ok()
+}
+
+/// URLs for every number in `range`, expanded only as they are needed
+///
+/// The first URL is checked right away, so a malformed template fails before
+/// any request is sent.
+fn expand_url_template(template: UrlTemplate, range: RangeInclusive<u64>) -> Result<impl Iterator<Item = Result<Url>>> {
+    let parse = |url: String| {
+        Url::parse(&url).with_context(|| { format!("Invalid URL \"{}\" expanded from --url-template", url) })
+    };
+    parse(template.expand(*range.start()))?;
+    Ok(range.map(move |n| { parse(template.expand(n)) }))
+}
+
+/// Read the string for a --data value of "@SOURCE" from file SOURCE, or
+/// from stdin if it is "-", removing carriage returns and newlines
+async fn read_data_arg(source: &str) -> Result<String> {
+    let mut data = String::new();
+    if source == "-" {
+        io::stdin().read_to_string(&mut data).await.context("Failed to read --data from stdin")?;
+    } else {
+        data = tokio::fs::read_to_string(source)
+            .await
+            .with_context(|| { format!("Failed to read --data from file {}", source) })?;
+    }
+    data.retain(|c| { c != '\r' && c != '\n' });
+    Ok(data)
+}
+
+fn read_url_file(path: &Path) -> Result<Vec<Url>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| { format!("Failed to read URL file {}", path.display()) })?;
+
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| { (i, line.trim()) })
+        .filter(|(_, line)| { !line.is_empty() && !line.starts_with('#') })
+        .map(|(i, line)| {
+            Url::parse(line).with_context(|| {
+                format!("Invalid URL \"{}\" on line {} of {}", line, i + 1, path.display())
+            })
+        })
+        .collect()
+}
+
+/// Perform the request, returning the exit code on success
+///
+/// If `mux` is given, the request is sent over its shared connection rather
+/// than over a connection of its own. Once `interrupt` is cancelled, the
+/// request is aborted, and any output file it was writing removed.
+async fn execute(cli: &Cli, mux: Option<&Multiplexer>, interrupt: &CancellationToken) -> Result<u8> {
+    if cli.server_info {
+        return until_interrupted(interrupt, query_server_info(cli)).await;
+    }
+
+    let mut stats = Stats::new();
+    let mut cli = Cow::Borrowed(cli);
+    if cli.keep_cookies() {
+        let cookies = cli.read_cookies().unwrap_or_else(|e| {
+            // Like a missing file, an unreadable one just means no cookies
+            eprintln!("Warning: {:#}", e);
+            Vec::new()
+        });
+        cli.to_mut().cookies = cookies;
+    }
+
+    let result = follow_redirects(&mut cli, mux, interrupt, &mut stats).await;
+    if let Some(path) = cli.cookie_jar.as_ref().filter(|_| { !cli.dry_run }) {
+        write_jar(path, &cli.cookies).with_context(|| { format!("Failed to write cookies to {}", path.display()) })?;
+    }
+    let status = result?;
+
+    stats.time_total = Some(stats.elapsed());
+    if cli.output_format == OutputFormat::Json && !cli.dry_run {
+        println!("{}", json_report(cli.url.as_ref(), &stats));
+    }
+
+    if let Some(format) = cli.write_out.as_ref().filter(|_| { !cli.dry_run }) {
+        for (target, output) in render(format, &stats) {
+            match target {
+                Target::Stdout => print!("{}", output),
+                Target::Stderr => eprint!("{}", output),
+            }
+        }
+    }
+
+    if cli.appstatus_exit {
+        return Ok(stats.app_status.map_or(0, |app_status| { app_status.min(255) as u8 }));
+    }
+
+    Ok(match status {
+        Some(status) if cli.exit_code_from_status => status_exit_code(status),
+        _ => 0,
+    })
+}
+
+/// Perform the request and follow any redirects, returning the status of
+/// the last response if known
+///
+/// If cookies are kept, those set by each response are added to `cli`.
+async fn follow_redirects(
+    cli: &mut Cow<'_, Cli>,
+    mux: Option<&Multiplexer>,
+    interrupt: &CancellationToken,
+    stats: &mut Stats
+) -> Result<Option<u16>> {
+    let mut visited = HashSet::new();
+    loop {
+        // Only cookies set by this response are to be kept
+        stats.headers = Headers::default();
+        let result = tokio::select! {
+            biased;
+            _ = interrupt.cancelled() => None,
+            result = execute_once(cli, mux, stats) => Some(result),
+        };
+        let Some(result) = result else {
+            if let Some(path) = stats.partial_output.take() {
+                // Best effort; being interrupted is what matters
+                let _ = tokio::fs::remove_file(path).await;
+            }
+            return Err(FcgiCliError::Interrupted.into());
+        };
+        if cli.keep_cookies() {
+            let url = cli.url.clone();
+            store_cookies(&mut cli.to_mut().cookies, url.as_ref(), &stats.headers);
+        }
+        let status = result.map_err(describe_connection_error)?;
+        let Some(location) = stats.redirect_url.take() else {
+            return Ok(status);
+        };
+
+        if stats.num_redirects >= cli.max_redirs {
+            bail!("Maximum number of redirects ({}) followed", cli.max_redirs);
+        }
+        if let Some(url) = cli.url.as_ref() {
+            visited.insert(url.clone());
+        }
+        if visited.contains(&location) {
+            bail!("Redirect loop detected at {}", location);
+        }
+        if cli.verbose > 0 {
+            eprintln!("{} Following redirect to {}", cli.colors().marker("*"), location);
+        }
+
+        stats.num_redirects += 1;
+        let mut next = Cli { url: Some(location), ..cli.as_ref().clone() };
+        if status == Some(303) {
+            next.request_method = "GET".to_string();
+            next.data.clear();
+            next.data_env = None;
+            next.data_urlencode.clear();
+            next.form_parts.clear();
+            next.data_file = None;
+            next.data_from_stdin = false;
+        }
+        *cli = Cow::Owned(next);
+    }
+}
+
+/// Perform a single request, returning its status if known
+async fn execute_once(cli: &Cli, mux: Option<&Multiplexer>, stats: &mut Stats) -> Result<Option<u16>> {
+    let mut params = if cli.cgi {
+        // The web server invoking us has already set up the environment, so
+        // do not fill in any defaults
+        let mut params = Params::default();
+        params.clear();
+        params
+    } else {
+        Params::default()
+            .gateway_interface("CGI/1.1")
+            .server_software(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+            .server_protocol("HTTP/1.1")
+    };
+
+    params = params.set_from_env(env::vars().flat_map(|(name, value)| {
+            cli.env_param_names(&name)
+                .into_iter()
+                .map(move |param| { (param, value.clone()) })
+        }));
+
+    for path in cli.env_files.iter() {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| { format!("Failed to read {}", path.display()) })?;
+        let vars = parse_env_file(&contents)
+            .map_err(|e| { anyhow!("Malformed env file {}: {}", path.display(), e) })?;
+        params = params.set_from_env(vars);
+    }
+
+    if let Some(credentials) = cli.credentials.as_ref() {
+        let password = credentials.password().map_err(|e| { anyhow!("Failed to read password: {}", e) })?;
+        params = params.set_basic_auth(&credentials.user, &password);
+    }
+
+    if cli.cgi {
+        for (name, value) in cli.params.iter() {
+            params.insert(name.clone().into(), value.clone().into());
+        }
+    } else {
+        cli.check_method()?;
+        cli.check_script_name()?;
+        params = params.set_from_cli(cli);
+        if let Some(offset) = cli.resume_offset()? {
+            if params.get("HTTP_RANGE").is_none() {
+                params.insert("HTTP_RANGE".into(), format!("bytes={}-", offset).into());
+            }
+        }
+    }
+
+    cli.check_role()?;
+    let transport = cli.transport()?;
+
+    if cli.dry_run {
+        print_dry_run(cli, &transport, &mut params).await?;
+        return Ok(None);
+    }
+
+    // The body is only opened once connected, so report an unreadable file
+    // before failing to connect could hide it
+    if let Some(path) = cli.data_file.as_ref().filter(|p| { p.as_os_str() != "-" }) {
+        tokio::fs::File::open(path)
+            .await
+            .with_context(|| { format!("Failed to open data file {}", path.display()) })?;
+    }
+
+    let mut attempt = 0u32;
+    let (status, response) = loop {
+        let connected = match mux {
+            Some(mux) => mux.open().map_err(Into::into),
+            None => connect(cli, &transport).await,
+        };
+        let error = match connected {
+            Ok(stream) => {
+                stats.time_connect = Some(stats.elapsed());
+                match perform_request(cli, &transport, stream, &mut params, stats).await {
+                    Ok(result) => break result,
+                    Err(e) if cli.retry_all_errors => e,
+                    Err(e) => return Err(e),
+                }
+            }
+            Err(e) if is_retryable_connect_error(&e) => e,
+            Err(e) => return Err(e),
+        };
+
+        if attempt >= cli.retry {
+            return Err(error);
+        }
+
+        let delay = cli.retry_delay.saturating_mul(2u32.saturating_pow(attempt));
+        eprintln!(
+            "Warning: {}. Will retry in {} ms. {} retries left.",
+            error,
+            delay.as_millis(),
+            cli.retry - attempt
+        );
+        time::sleep(delay).await;
+        attempt += 1;
+    };
+
+    let end_request = response.end_request();
+    stats.app_status = end_request.map(|end_request| { end_request.app_status });
+    // The response is still used, so --appstatus-exit applies as usual
+    if let Some(message) = end_request.and_then(|end_request| { end_request.protocol_status_message() }) {
+        eprintln!("Warning: {}", message);
+    }
+    stats.size_stderr = response.stderr_length();
+
+    if cli.fail_on_stderr && response.stderr_length() > 0 {
+        bail!("Application wrote {} bytes to FCGI_STDERR", response.stderr_length());
+    }
+
+    Ok(status)
+}
+
+/// Explain a failure of the connection to the application in terms of what
+/// likely happened. Other errors are returned as is.
+fn describe_connection_error(error: anyhow::Error) -> anyhow::Error {
+    let failure = error
+        .downcast_ref::<io::Error>()
+        .and_then(|e| { e.get_ref() })
+        .and_then(|e| { e.downcast_ref::<ConnectionError>() });
+    let message = match failure {
+        Some(ConnectionError::Reset) => {
+            "The FastCGI server reset the connection during the request (connection reset by peer). \
+            The application may have crashed.".to_string()
+        }
+        Some(ConnectionError::UnexpectedEof) => {
+            "The FastCGI server closed the connection before ending the request \
+            (unexpected EOF before FCGI_END_REQUEST). The application may have crashed or exited.".to_string()
+        }
+        Some(ConnectionError::Framing(message)) => {
+            format!(
+                "The FastCGI server sent an invalid response (protocol framing error: {}). \
+                Check that the address refers to a FastCGI server.",
+                message
+            )
+        }
+        None => return error,
+    };
+    error.context(message)
+}
+
+async fn connect(cli: &Cli, transport: &Transport) -> Result<Box<dyn Stream>> {
+    if cli.verbose > 0 {
+        let marker = cli.colors().marker("*");
+        match transport {
+            Transport::Tcp(address) => eprintln!("{} Connecting to {} over TCP", marker, address),
+            Transport::Unix(path) => eprintln!("{} Connecting to Unix socket {}", marker, path.display()),
+            Transport::Abstract(name) => eprintln!("{} Connecting to abstract Unix socket @{}", marker, name),
+            Transport::Fd(fd) => eprintln!("{} Using inherited socket on file descriptor {}", marker, fd),
+        }
+    }
+
+    let connected = if let Some(connect_timeout) = cli.connect_timeout {
+        time::timeout(connect_timeout, transport.connect(cli.interface))
+            .await
+            .unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Timed out connecting to {} after {} seconds", transport, connect_timeout.as_secs_f64())
+                ))
+            })
+    } else {
+        transport.connect(cli.interface).await
+    };
+    Ok(connected.map_err(FcgiCliError::Connect)?)
+}
+
+/// Print what would be sent where, for --dry-run
+async fn print_dry_run(cli: &Cli, transport: &Transport, params: &mut Params<'_>) -> Result<()> {
+    let mut body = open_request_body(cli, params).await?;
+    if let Some(coding) = cli.compress_request {
+        body = compress_request_body(coding, body, params).await?;
+    }
+    drop(body);
+    open_filter_data(cli, params).await?;
+
+    let body_source = if cli.cgi {
+        Cow::Borrowed("stdin")
+    } else if !cli.form_parts.is_empty() {
+        Cow::Borrowed("multipart form")
+    } else if cli.data_env.is_some() {
+        Cow::Borrowed("environment")
+    } else if cli.inline_data().is_some() {
+        Cow::Borrowed("command line")
+    } else if let Some(path) = cli.data_file.as_ref().filter(|p| { p.as_os_str() != "-" }) {
+        Cow::Owned(path.display().to_string())
+    } else {
+        Cow::Borrowed("stdin")
+    };
+    let output = match cli.real_output_file_name()? {
+        Some(file_name) => cli.resolve_output_path(file_name).display().to_string(),
+        None if cli.silent => "none".to_string(),
+        None => "stdout".to_string(),
+    };
+
+    println!("Address: {}", transport);
+    println!("Parameters:");
+    let mut sorted: Vec<_> = params.iter().collect();
+    sorted.sort();
+    for (key, value) in sorted {
+        println!("  {}={}", key, value);
+    }
+    match params.get("CONTENT_LENGTH") {
+        Some(length) => println!("Body: {} bytes from {}", length, body_source),
+        None => println!("Body: none"),
+    }
+    println!("Output: {}", output);
+    if let Some(file_name) = cli.response_headers_dump_file.as_ref() {
+        match file_name.as_os_str() == "-" {
+            true => println!("Headers: stdout"),
+            false => println!("Headers: {}", cli.resolve_output_path(file_name).display()),
+        }
+    }
+    match cli.stderr_file_name.as_ref() {
+        Some(file_name) if file_name.as_os_str() == "stdout" => println!("Stderr: stdout"),
+        Some(file_name) if file_name.as_os_str() != "-" => {
+            println!("Stderr: {}", cli.resolve_output_path(file_name).display())
+        }
+        _ => println!("Stderr: stderr"),
+    }
+
+    Ok(())
+}
+
+fn print_params(params: &Params, colors: Colors) {
+    let mut params: Vec<_> = params.iter().collect();
+    params.sort();
+    for (key, value) in params {
+        eprintln!("{} {}={}", colors.marker("*"), colors.name(key), value);
+    }
+}
+
+fn print_record_header(direction: Direction, header: &RecordHeader, colors: Colors) {
+    let prefix = match direction {
+        Direction::Sent => ">",
+        Direction::Received => "<",
+    };
+    let bytes: Vec<String> = header.to_bytes().iter().map(|b| { format!("{:02x}", b) }).collect();
+    eprintln!("{} {}", colors.marker(prefix), bytes.join(" "));
+}
+
+/// Whether connecting may succeed on a later attempt, e.g. because the
+/// server is still starting up
+fn is_retryable_connect_error(error: &anyhow::Error) -> bool {
+    let Some(FcgiCliError::Connect(e)) = error.downcast_ref() else {
+        return false;
+    };
+
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotFound
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::Interrupted
+    )
+}
+
+/// Send the request over `stream` and write the response body
+///
+/// The request body is opened anew on each call, but note that a body read
+/// from stdin cannot be sent again.
+async fn perform_request<S: Stream>(
+    cli: &Cli,
+    transport: &Transport,
+    stream: S,
+    params: &mut Params<'_>,
+    stats: &mut Stats
+) -> Result<(Option<u16>, ResponseStream<S>)> {
+    let mut input_stream = open_request_body(cli, params).await?;
+    if let Some(coding) = cli.compress_request {
+        input_stream = compress_request_body(coding, input_stream, params).await?;
+    }
+    if cli.progress {
+        let total = params.get("CONTENT_LENGTH").and_then(|l| { l.parse().ok() });
+        input_stream = Box::pin(ProgressReader::new(input_stream, Progress::new("Uploaded", total)));
+    }
+    let mut data_stream = open_filter_data(cli, params).await?;
+
+    if cli.verbose > 0 {
+        print_params(params, cli.colors());
+    }
+
+    let request = async {
+        let mut client = Client::new(stream).role(cli.role).keep_conn(cli.keepalive);
+        if let Some(request_id) = cli.request_id {
+            client = client.request_id(request_id);
+        }
+        let mut trace = match cli.trace_file.as_ref() {
+            Some(path) => Some(
+                Trace::open(path, cli.color).with_context(|| { format!("Failed to open trace file {}", path.display()) })?
+            ),
+            None => None,
+        };
+        let print_headers = cli.verbose > 1;
+        let colors = cli.colors();
+        if print_headers || trace.is_some() {
+            client = client.observer(Box::new(move |direction, header, content| {
+                if print_headers {
+                    print_record_header(direction, header, colors);
+                }
+                if let Some(trace) = trace.as_mut() {
+                    trace.record(direction, header, content);
+                }
+            }));
+        }
+
+        // Output to stdout goes through a single writer, to keep it in order
+        let mut stdout = SharedWriter::new(io::stdout());
+        let stderr_writer = open_stderr_output(cli, stdout.clone()).await?;
+        let mut response = client.execute(params, &mut input_stream, &mut data_stream).await?;
+        response.forward_stderr(stderr_writer);
+        let result = handle_response_stdout(cli, params, &mut response, stats, stdout.clone()).await;
+        // Read the rest of the response, if returned early, so any output on
+        // FCGI_STDERR is still forwarded. Any error here is secondary.
+        while let Ok(Some(_)) = response.next_stdout().await {}
+        // Shutting down stdout does not wait for a pending write to complete,
+        // so the last chunk could still be lost on exit
+        stdout.flush().await?;
+        Ok((result?, response))
+    };
+
+    if let Some(max_time) = cli.max_time {
+        time::timeout(max_time, request)
+            .await
+            .map_err(|_| {
+                anyhow!("Request to {} did not complete within {} seconds", transport, max_time.as_secs_f64())
+            })?
+    } else {
+        request.await
+    }
+}
+
+/// Map an HTTP status code to an exit code
+///
+/// 2xx maps to 0 and 3xx, 4xx and 5xx map to 3, 4 and 5 respectively. Any
+/// other status maps to 1.
+fn status_exit_code(status: u16) -> u8 {
+    match status {
+        200..=299 => 0,
+        300..=599 => (status / 100) as u8,
+        _ => 1,
+    }
+}
+
+/// Open the request body, setting CONTENT_LENGTH if its length can be
+/// determined and it was not given explicitly.
+///
+/// In the authorizer role, the body is empty, and the parameters describing
+/// it and the script are removed.
+async fn open_request_body<'a>(cli: &'a Cli, params: &mut Params<'_>) -> Result<Pin<Box<dyn AsyncRead + 'a>>> {
+    if cli.role == Role::Authorizer {
+        for name in AUTHORIZER_EXCLUDED_PARAMS.iter() {
+            params.remove(*name);
+        }
+        return Ok(Box::pin(io::empty()));
+    }
+
+    let explicit_length = params.get("CONTENT_LENGTH").is_some();
+    let set_length = !explicit_length && !cli.no_content_length;
+
+    if cli.cgi {
+        // Never read past the body, as a CGI program must not
+        let length = match params.get("CONTENT_LENGTH") {
+            Some(length) => parse_content_length(length)?,
+            None => 0,
+        };
+        return Ok(Box::pin(io::stdin().take(length as u64)));
+    }
+
+    if !cli.form_parts.is_empty() {
+        let multipart = Multipart::new(cli.form_parts.clone());
+        if params.get("CONTENT_TYPE").is_none() {
+            params.insert("CONTENT_TYPE".into(), multipart.content_type().into());
+        }
+        let (length, reader) = multipart.open().await?;
+        if set_length {
+            params.insert("CONTENT_LENGTH".into(), length.to_string().into());
+        }
+        return Ok(reader);
+    }
+
+    if let Some(data) = cli.inline_data() {
+        check_data_length(params, data.len())?;
+        return Ok(match data {
+            Cow::Borrowed(data) => Box::pin(data.as_bytes()),
+            Cow::Owned(data) => Box::pin(Cursor::new(data.into_bytes())),
+        });
+    }
+
+    if let Some(path) = cli.data_file.as_ref().filter(|p| { p.as_os_str() != "-" }) {
+        let file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| { format!("Failed to open data file {}", path.display()) })?;
+        if set_length {
+            let length = file.metadata().await?.len();
+            params.insert("CONTENT_LENGTH".into(), length.to_string().into());
+        }
+        return Ok(Box::pin(file));
+    }
+
+    if explicit_length {
+        return stdin_body(cli).await;
+    }
+
+    let stdin_requested = cli.data_file.is_some();
+    let method_has_body = !matches!(
+        params.get("REQUEST_METHOD").map(|m| { m.as_ref() }),
+        Some("GET" | "HEAD")
+    );
+
+    if cli.no_content_length && (stdin_requested || method_has_body) {
+        return stdin_body(cli).await;
+    }
+
+    if !cli.data_from_stdin && (stdin_requested || method_has_body) {
+        if let Some(length) = stdin_file_length() {
+            params.insert("CONTENT_LENGTH".into(), length.to_string().into());
+            return stdin_body(cli).await;
+        }
+    }
+
+    if cli.data_from_stdin || stdin_requested {
+        let data = read_stdin_body(cli).await?;
+        params.insert("CONTENT_LENGTH".into(), data.len().to_string().into());
+        return Ok(Box::pin(Cursor::new(data)));
+    }
+
+    Ok(Box::pin(io::empty()))
+}
+
+/// Read the request body into memory and compress it, updating
+/// CONTENT_LENGTH accordingly.
+async fn compress_request_body<'a>(
+    coding: ContentCoding,
+    mut body: Pin<Box<dyn AsyncRead + 'a>>,
+    params: &mut Params<'_>
+) -> Result<Pin<Box<dyn AsyncRead + 'a>>> {
+    let mut data = Vec::new();
+    body.read_to_end(&mut data).await.context("Failed to read request body")?;
+    if data.is_empty() {
+        return Ok(Box::pin(io::empty()));
+    }
+
+    let compressed = coding.encode(&data).await.context("Failed to compress request body")?;
+    params.insert("HTTP_CONTENT_ENCODING".into(), coding.name().into());
+    params.insert("CONTENT_LENGTH".into(), compressed.len().to_string().into());
+    Ok(Box::pin(Cursor::new(compressed)))
+}
+
+/// Open the file to send on the FCGI_DATA stream in the filter role, and set
+/// the parameters describing it.
+async fn open_filter_data(cli: &Cli, params: &mut Params<'_>) -> Result<Pin<Box<dyn AsyncRead>>> {
+    if cli.role != Role::Filter {
+        return Ok(Box::pin(io::empty()));
+    }
+
+    let (stream, length, last_mod): (Pin<Box<dyn AsyncRead>>, u64, u64) =
+        if let Some(path) = cli.filter_data_file.as_ref() {
+            let file = tokio::fs::File::open(path)
+                .await
+                .with_context(|| { format!("Failed to open filter data file {}", path.display()) })?;
+            let metadata = file.metadata().await?;
+            let last_mod = metadata
+                .modified()
+                .ok()
+                .and_then(|t| { t.duration_since(UNIX_EPOCH).ok() })
+                .map_or(0, |d| { d.as_secs() });
+            (Box::pin(file), metadata.len(), last_mod)
+        } else {
+            (Box::pin(io::empty()), 0, 0)
+        };
+
+    params.insert("FCGI_DATA_LENGTH".into(), length.to_string().into());
+    params.insert("FCGI_DATA_LAST_MOD".into(), last_mod.to_string().into());
+
+    Ok(stream)
+}
+
+/// Reader for a request body on stdin
+///
+/// When following redirects, the body is read into memory instead of being
+/// streamed, so it can be sent again.
+async fn stdin_body<'a>(cli: &Cli) -> Result<Pin<Box<dyn AsyncRead + 'a>>> {
+    if cli.follow_location {
+        Ok(Box::pin(Cursor::new(read_stdin_body(cli).await?)))
+    } else {
+        Ok(Box::pin(io::stdin()))
+    }
+}
+
+/// Read the request body from stdin, or take the copy kept from an earlier
+/// request of a redirect chain
+async fn read_stdin_body(cli: &Cli) -> Result<Vec<u8>> {
+    if let Some(data) = cli.stdin_body.get() {
+        return Ok(data.clone());
+    }
+
+    let mut data = Vec::new();
+    io::stdin().read_to_end(&mut data).await.context("Failed to read request body from stdin")?;
+    if cli.follow_location {
+        Ok(cli.stdin_body.get_or_init(|| { data }).clone())
+    } else {
+        Ok(data)
+    }
+}
+
+/// Size of stdin, if it is a regular file
+fn stdin_file_length() -> Option<u64> {
+    let fd = std::io::stdin().as_fd().try_clone_to_owned().ok()?;
+    let metadata = File::from(fd).metadata().ok()?;
+    metadata.is_file().then_some(metadata.len())
+}
+
+fn check_data_length(params: &Params, data_length: usize) -> Result<()> {
+    if let Some(str_content_length) = params.get("CONTENT_LENGTH") {
+        let content_length: usize = parse_content_length(str_content_length.borrow())?;
+        if data_length < content_length {
+            bail!("Insufficient input. Received {} bytes of data, but expected \
+                at least {} bytes because of explicit CONTENT_LENGTH parameter.",
+                data_length,
+                content_length
+            );
+        };
+    };
+
+    Ok(())
+}
+
+fn parse_content_length(str_content_length: &str) -> Result<usize> {
+    str_content_length
+        .parse()
+        .context(format!(
+            "Failed to parse value of CONTENT_LENGTH. Expected unsigned integer, got \"{}\".",
+            str_content_length
+        ))
+}
+
+/// Open an output file, replacing its contents unless `append` is set, and
+/// return it along with its path
+///
+/// With --no-clobber, an existing file is not replaced. Instead, ".1", ".2",
+/// etc. is appended to the file name until it does not exist yet.
+async fn open_output_file(
+    cli: &Cli,
+    file_name: impl AsRef<Path>,
+    append: bool
+) -> Result<(Pin<Box<dyn io::AsyncWrite>>, PathBuf)> {
+    let path = cli.resolve_output_path(file_name);
+
+    if let Some(dir) = path.parent().filter(|d| { !d.as_os_str().is_empty() }) {
+        if cli.create_dirs {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| { format!("Failed to create directory {}", dir.display()) })?;
+        } else if tokio::fs::metadata(dir).await.is_err_and(|e| { e.kind() == io::ErrorKind::NotFound }) {
+            bail!("Output directory {} does not exist. Use --create-dirs to create it.", dir.display());
+        }
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(!append).append(append);
+    if !cli.no_clobber || append {
+        let file = options
+            .open(&path)
+            .await
+            .with_context(|| { format!("Failed to open output file {}", path.display()) })?;
+        return Ok((Box::pin(file), path));
+    }
+
+    // Creating the file fails if it exists, so requests running in parallel
+    // cannot end up with the same file
+    options.create_new(true);
+    let mut suffix = 0;
+    loop {
+        let candidate = if suffix == 0 {
+            path.clone()
+        } else {
+            let mut name = path.clone().into_os_string();
+            name.push(format!(".{}", suffix));
+            PathBuf::from(name)
+        };
+        match options.open(&candidate).await {
+            Ok(file) => {
+                if suffix > 0 && cli.verbose > 0 {
+                    eprintln!(
+                        "{} Output file {} exists, writing to {}",
+                        cli.colors().marker("*"),
+                        path.display(),
+                        candidate.display()
+                    );
+                }
+                return Ok((Box::pin(file), candidate));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+            Err(e) => {
+                return Err(e).with_context(|| { format!("Failed to open output file {}", candidate.display()) })
+            }
+        }
+    }
+}
+
+/// Write the response body to its destination
+///
+/// Returns the response status, if needed and found in the headers. Output
+/// to stdout is written to `stdout`, shared with output on FCGI_STDERR.
+async fn handle_response_stdout<S: AsyncRead + Unpin>(
+    cli: &Cli,
+    params: &Params<'_>,
+    response: &mut ResponseStream<S>,
+    stats: &mut Stats,
+    stdout: SharedWriter<io::Stdout>
+) -> Result<Option<u16>> {
+    let Some(mut data) = response.next_stdout().await? else {
+        return Ok(None);
+    };
+    stats.time_starttransfer = Some(stats.elapsed());
+
+    if cli.need_parse_header() {
+        // Read just enough to locate the end of the header block. If it
+        // cannot be found within --max-header-size, header parsing below
+        // fails.
+        while find_header_end(&data).is_none() && data.len() as u64 <= cli.max_header_size {
+            match response.next_stdout().await? {
+                Some(chunk) => data.extend(chunk),
+                None => break,
+            }
+        }
+    }
+
+    let resume_offset = cli.resume_offset()?;
+    let mut failure = None;
+    let mut status = None;
+    let mut coding = None;
+    let data = data.as_slice();
+    let (head, body) = if cli.need_parse_header() {
+        let (body, headers) = match parse_headers(data, cli.header_parse_options()) {
+            Ok(parsed) => parsed,
+            Err(e) if e.code == nom::error::ErrorKind::TooLarge && cli.require_headers() => {
+                return Err(FcgiCliError::HeaderParse(format!(
+                    "Response header exceeds the maximum size of {} bytes. Use --max-header-size to raise the limit.",
+                    cli.max_header_size
+                )).into());
+            }
+            Err(_) if !cli.require_headers() => {
+                eprintln!("Warning: Malformed response header. Treating the entire response as body.");
+                (data, Headers::default())
+            }
+            Err(_) => return Err(FcgiCliError::HeaderParse("Malformed response header.".to_string()).into()),
+        };
+
+        if cli.need_status() {
+            status = Some(response_status(&headers)?);
+        } else if body.len() < data.len() {
+            // Only informational, so do not fail on an invalid status
+            status = response_status(&headers).ok();
+        }
+        stats.response_code = status;
+        stats.size_header = (data.len() - body.len()) as u64;
+        stats.headers = headers.clone();
+
+        if cli.follow_location {
+            if let Some(location) = redirect_location(cli, &headers, status)? {
+                stats.redirect_url = Some(location);
+                // Only the response to the final request is output
+                while response.next_stdout().await?.is_some() {}
+                return Ok(status);
+            }
+        }
+
+        if let (Some(threshold), Some(status)) = (cli.fail_threshold(), status) {
+            if status >= threshold {
+                let error = anyhow::Error::from(FcgiCliError::Status(status));
+                if cli.response_status_fail_with_body {
+                    failure = Some(error);
+                } else {
+                    return Err(error);
+                }
+            }
+        };
+
+        if let Some(offset) = resume_offset {
+            check_resumed(offset, status, &headers)?;
+        }
+
+        if let Some(file_name) = cli.response_headers_dump_file.as_ref() {
+            let mut hdr_stream: Pin<Box<dyn io::AsyncWrite>> = if file_name.as_os_str() == "-" {
+                Box::pin(stdout.clone())
+            } else {
+                open_output_file(cli, file_name, false).await?.0
+            };
+            // This flushes the headers, so they precede the body on stdout
+            let dump = match cli.dump_header_format {
+                HeaderDumpFormat::Raw => Cow::Borrowed(&data[..data.len() - body.len()]),
+                HeaderDumpFormat::Json => {
+                    let mut json = serde_json::to_vec(&headers_json(&headers))?;
+                    json.push(b'\n');
+                    Cow::Owned(json)
+                }
+            };
+            io::copy(&mut dump.as_ref(), &mut hdr_stream).await?;
+        }
+
+        if cli.decompress() && !cli.head {
+            if let Some(value) = headers.get("content-encoding") {
+                coding = ContentCoding::from_header(value);
+                if coding.is_none() && !value.trim().eq_ignore_ascii_case("identity") {
+                    eprintln!("Warning: Not decompressing unsupported Content-Encoding \"{}\"", value);
+                }
+            }
+        }
+
+        let head = &data[..data.len() - body.len()];
+        if !cli.include_headers() {
+            (Cow::Borrowed(&[][..]), body)
+        } else {
+            let mut removed_fields = Vec::new();
+            if coding.is_some() {
+                removed_fields.extend(["Content-Encoding", "Content-Length"]);
+            }
+            if cli.nph || cli.strip_status_header() {
+                removed_fields.push("Status");
+            }
+
+            let mut head = if removed_fields.is_empty() {
+                Cow::Borrowed(head)
+            } else {
+                Cow::Owned(remove_header_fields(head, &removed_fields))
+            };
+
+            if let (true, Some(status)) = (cli.nph, status) {
+                let protocol = params.get("SERVER_PROTOCOL").map_or("HTTP/1.1", |p| { p.as_ref() });
+                let reason = headers
+                    .get("status")
+                    .and_then(|s| { s.trim().split_once(char::is_whitespace) })
+                    .map(|(_, reason)| { reason.trim() })
+                    .or_else(|| { reason_phrase(status) })
+                    .unwrap_or("");
+                let status_line = format!("{} {} {}\r\n", protocol, status, reason);
+                let mut nph_head = cli.header_charset.encode(&status_line);
+                nph_head.extend(crlf_line_endings(&head));
+                head = Cow::Owned(nph_head);
+            }
+
+            (head, body)
+        }
+    } else {
+        (Cow::Borrowed(&[][..]), data)
+    };
+
+    let content_length = stats.headers.get("content-length").and_then(|l| { l.trim().parse::<u64>().ok() });
+    let output_file_name = cli.real_output_file_name()?;
+    // Do not trust the declared length with more than a modest allocation
+    let mut captured_body = Vec::with_capacity(content_length.unwrap_or(0).min(MAX_PREALLOCATION) as usize);
+    // File resumed by -C, --continue-at
+    let mut appended_file = None;
+    let mut out_stream: Pin<Box<dyn io::AsyncWrite + '_>> =
+        if let Some(file_name) = output_file_name.as_ref() {
+            let append = resume_offset.is_some();
+            let (out_stream, path) = open_output_file(cli, file_name, append).await?;
+            if append {
+                appended_file = Some(path);
+            } else {
+                stats.partial_output = Some(path);
+            }
+            out_stream
+        } else if cli.silent {
+            Box::pin(io::sink())
+        } else if cli.output_format == OutputFormat::Json {
+            Box::pin(&mut captured_body)
+        } else {
+            Box::pin(stdout)
+        };
+
+    out_stream.write_all(&head).await?;
+    if cli.head {
+        // Discard any body the server sent anyway
+        out_stream.flush().await?;
+        out_stream = Box::pin(io::sink());
+    }
+    if let Some(coding) = coding {
+        out_stream = coding.decoder(out_stream);
+    }
+
+    let mut progress = cli.progress.then(|| { Progress::new("Downloaded", content_length) });
+    let mut too_large = false;
+    let copied = async {
+        let mut chunk = body.to_vec();
+        loop {
+            stats.size_download += chunk.len() as u64;
+            if let Some(progress) = progress.as_mut() {
+                progress.advance(chunk.len() as u64);
+            }
+            if cli.max_filesize.is_some_and(|max| { stats.size_download > max }) {
+                too_large = true;
+                return Ok(());
+            }
+            out_stream.write_all(&chunk).await?;
+            if cli.no_buffer {
+                out_stream.flush().await?;
+            }
+            match response.next_stdout().await? {
+                Some(next) => chunk = next,
+                None => break,
+            }
+        }
+        if let Some(progress) = progress.as_mut() {
+            progress.finish();
+        }
+        out_stream.shutdown().await
+    }.await;
+    if too_large {
+        // Complete any pending write before cleaning up. Cleaning up is best
+        // effort; the error below is what matters.
+        let _ = out_stream.shutdown().await;
+        drop(out_stream);
+        if let Some(path) = stats.partial_output.take() {
+            let _ = tokio::fs::remove_file(path).await;
+        } else if let (Some(path), Some(offset)) = (appended_file, resume_offset) {
+            // Keep what was downloaded before, only dropping what was appended
+            if let Ok(file) = OpenOptions::new().write(true).open(path).await {
+                let _ = file.set_len(offset).await;
+            }
+        }
+        bail!("Response body exceeds the maximum file size of {} bytes", cli.max_filesize.unwrap_or_default());
+    }
+    match coding {
+        Some(_) => copied.context("Failed to decompress response body")?,
+        None => copied?,
+    }
+    if let Some(content_length) = content_length.filter(|_| { !cli.head }) {
+        if content_length != stats.size_download {
+            let message = format!(
+                "Response body is {} bytes long, but Content-Length is {}",
+                stats.size_download,
+                content_length
+            );
+            if cli.strict_content_length {
+                bail!("{}. The application may have crashed.", message);
+            }
+            eprintln!("Warning: {}.", message);
+        }
+    }
+    drop(out_stream);
+    stats.partial_output = None;
+    if cli.output_format == OutputFormat::Json && output_file_name.is_none() && !cli.silent {
+        stats.body = Some(captured_body);
+    }
+
+    match failure {
+        Some(error) => Err(error),
+        None => Ok(status),
+    }
+}
+
+/// Add the cookies set by a response to a request for `url` to `jar`
+fn store_cookies(jar: &mut Vec<Cookie>, url: Option<&Url>, headers: &Headers) {
+    for value in headers.get_all("set-cookie") {
+        match parse_set_cookie(value, url) {
+            Ok(cookie) => merge(jar, cookie),
+            Err(e) => eprintln!("Warning: Ignoring Set-Cookie header \"{}\": {}", value, e),
+        }
+    }
+}
+
+/// Check that the response is the rest of the file from `offset`, rather
+/// than the entire file, which should not be appended to the output file
+fn check_resumed(offset: u64, status: Option<u16>, headers: &Headers) -> Result<()> {
+    if status != Some(206) {
+        bail!(
+            "Server did not honor the range request (status: {}), so the download cannot be resumed.",
+            status.map_or("unknown".to_string(), |s| { s.to_string() })
+        );
+    }
+
+    // Content-Range: bytes START-END/LENGTH
+    let start = headers
+        .get("content-range")
+        .and_then(|range| { range.trim().strip_prefix("bytes ") })
+        .and_then(|range| { range.split_once('-') })
+        .and_then(|(start, _)| { start.trim().parse::<u64>().ok() });
+    if let Some(start) = start.filter(|&start| { start != offset }) {
+        bail!("Server returned a range starting at byte {}, but {} was requested.", start, offset);
+    }
+
+    Ok(())
+}
+
+/// URL to redirect to, if the response is a redirect
+fn redirect_location(cli: &Cli, headers: &Headers, status: Option<u16>) -> Result<Option<Url>> {
+    let Some(location) = headers.get("location") else {
+        return Ok(None);
+    };
+
+    // Without a Status header, this is either a local or a client redirect
+    if headers.get("status").is_some() && !matches!(status, Some(300..=399)) {
+        return Ok(None);
+    }
+
+    let base = cli.url
+        .as_ref()
+        .ok_or_else(|| { anyhow!("Cannot follow redirect to \"{}\" without a URL", location) })?;
+    let url = base
+        .join(location.trim())
+        .with_context(|| { format!("Invalid redirect location \"{}\"", location) })?;
+    Ok(Some(url))
+}
+
+fn response_status(headers: &Headers) -> Result<u16, FcgiCliError> {
+    headers
+        .get("status")
+        .map_or_else(|| { Ok(200u16) }, |s| {
+            let first_part = s.split_ascii_whitespace().next().unwrap_or("");
+            str::parse::<u16>(first_part)
+        })
+        .map_err(|_| { FcgiCliError::HeaderParse("While parsing response header 'Status'".to_string()) })
+}
+
+/// Standard reason phrase for common status codes
+fn reason_phrase(status: u16) -> Option<&'static str> {
+    Some(match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        422 => "Unprocessable Content",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => return None,
+    })
+}
+
+/// Open the destination of output on FCGI_STDERR, see --stderr
+///
+/// Output to stdout is written to `stdout`, shared with the response body.
+async fn open_stderr_output(cli: &Cli, stdout: SharedWriter<io::Stdout>) -> Result<Pin<Box<dyn io::AsyncWrite>>> {
+    let writer: Pin<Box<dyn io::AsyncWrite>> = match cli.stderr_file_name.as_ref() {
+        Some(file_name) if file_name.as_os_str() == "stdout" => Box::pin(stdout),
+        Some(file_name) if file_name.as_os_str() != "-" => open_output_file(cli, file_name, false).await?.0,
+        _ => Box::pin(io::stderr()),
+    };
+    Ok(match cli.stderr_prefix.as_ref() {
+        Some(prefix) => Box::pin(LinePrefixWriter::new(writer, prefix.as_bytes())),
+        None => writer,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_cli(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("fcgi").chain(args.iter().copied())).unwrap()
+    }
+
+    fn param<'p>(params: &'p Params, name: &str) -> Option<&'p str> {
+        params.get(name).map(|v| { v.as_ref() })
+    }
+
+    #[test]
+    fn cgi_mode_passes_server_variables() {
+        let cli = parse_cli(&["--cgi", "127.0.0.1:9000"]);
+        for var_name in ["SCRIPT_FILENAME", "DOCUMENT_ROOT", "REQUEST_URI", "HTTPS", "REDIRECT_STATUS", "SCRIPT_NAME"] {
+            assert!(cli.is_envvar_whitelisted(var_name), "{}", var_name);
+        }
+        assert!(!cli.is_envvar_whitelisted("PATH"));
+
+        let cli = parse_cli(&["127.0.0.1:9000"]);
+        assert!(!cli.is_envvar_whitelisted("SCRIPT_FILENAME"));
+        assert!(!parse_cli(&["--cgi", "--no-env", "127.0.0.1:9000"]).is_envvar_whitelisted("SCRIPT_FILENAME"));
+    }
+
+    #[tokio::test]
+    async fn authorizer_gets_no_body() {
+        let mut cli = parse_cli(&["--role", "authorizer", "--script", "/app.php", "--data", "x=1"]);
+        cli.url = Some(Url::parse("http://example.com/app.php/info").unwrap());
+        let mut params = Params::default().set_from_cli(&cli);
+        assert_eq!(param(&params, "CONTENT_LENGTH"), Some("3"));
+
+        let mut body = open_request_body(&cli, &mut params).await.unwrap();
+        let mut data = Vec::new();
+        body.read_to_end(&mut data).await.unwrap();
+        assert!(data.is_empty());
+        for name in AUTHORIZER_EXCLUDED_PARAMS.iter() {
+            assert_eq!(param(&params, name), None, "{}", name);
+        }
+        assert_eq!(param(&params, "REQUEST_URI"), Some("/app.php/info"));
+    }
+
+    #[test]
+    fn filter_data_requires_filter_role() {
+        assert!(parse_cli(&["--filter-data", "data.txt"]).check_role().is_err());
+        assert!(parse_cli(&["--filter-data", "data.txt", "--role", "authorizer"]).check_role().is_err());
+        assert!(parse_cli(&["--filter-data", "data.txt", "--role", "filter"]).check_role().is_ok());
+        assert!(parse_cli(&["--role", "filter"]).check_role().is_ok());
+    }
+
+    #[test]
+    fn keeps_https_from_environment() {
+        let mut cli = parse_cli(&[]);
+        cli.url = Some(Url::parse("http://example.com/").unwrap());
+        let params = Params::default().set_from_cli(&cli);
+        assert_eq!(param(&params, "HTTPS"), None);
+
+        let params = Params::default().set_from_env([("HTTPS", "on")]).set_from_cli(&cli);
+        assert_eq!(param(&params, "HTTPS"), Some("on"));
+    }
+
+    #[test]
+    fn parses_request_headers() {
+        assert_eq!(
+            parse_request_header("X-Forwarded-For: 10.0.0.1").unwrap(),
+            ("HTTP_X_FORWARDED_FOR".to_string(), "10.0.0.1".to_string())
+        );
+        assert_eq!(parse_request_header("Accept:").unwrap(), ("HTTP_ACCEPT".to_string(), String::new()));
+        assert!(parse_request_header("no colon").is_err());
+        assert!(parse_request_header(": value").is_err());
+        assert!(parse_request_header("Bad Name: value").is_err());
+    }
+
+    #[test]
+    fn resolves_output_path() {
+        let cli = parse_cli(&["--output-dir", "/tmp/out"]);
+        assert_eq!(cli.resolve_output_path("page.html"), Path::new("/tmp/out/page.html"));
+        assert_eq!(cli.resolve_output_path("/abs/page.html"), Path::new("/abs/page.html"));
+        let cli = parse_cli(&[]);
+        assert_eq!(cli.resolve_output_path("page.html"), Path::new("page.html"));
+    }
+
+    #[test]
+    fn derives_remote_file_name() {
+        // -O requires a URL argument, while the URL itself is set later on
+        let file_name = |default_file_name: bool, url: &str| {
+            let mut cli = parse_cli(&["-O", "127.0.0.1:9000", url]);
+            cli.default_file_name = default_file_name;
+            cli.url = Some(Url::parse(url).unwrap());
+            cli.real_output_file_name().map_err(|e| { e.to_string() })
+        };
+        assert_eq!(file_name(false, "http://x/a/b.txt"), Ok(Some(PathBuf::from("b.txt"))));
+        for url in ["http://x/", "http://x/a/"] {
+            let error = file_name(false, url).unwrap_err();
+            assert!(error.starts_with("Cannot derive an output file name"), "{}", error);
+            assert_eq!(file_name(true, url), Ok(Some(PathBuf::from(DEFAULT_FILE_NAME))));
+        }
+    }
+
+    #[test]
+    fn reads_response_status() {
+        let headers = parse_headers(b"Status: 404 Not Found\r\n\r\n", ParseOptions::default()).unwrap().1;
+        assert_eq!(response_status(&headers).unwrap(), 404);
+        let headers = parse_headers(b"Content-Type: text/plain\r\n\r\n", ParseOptions::default()).unwrap().1;
+        assert_eq!(response_status(&headers).unwrap(), 200);
+        let headers = parse_headers(b"Status: OK\r\n\r\n", ParseOptions::default()).unwrap().1;
+        assert!(matches!(response_status(&headers), Err(FcgiCliError::HeaderParse(_))));
+    }
+
+    #[test]
+    fn fails_from_threshold() {
+        assert_eq!(parse_cli(&[]).fail_threshold(), None);
+        assert_eq!(parse_cli(&["-f"]).fail_threshold(), Some(400));
+        assert_eq!(parse_cli(&["--fail-with-body"]).fail_threshold(), Some(400));
+        assert_eq!(parse_cli(&["--fail-with", "401"]).fail_threshold(), Some(401));
+    }
+
+    #[test]
+    fn checks_resumed_response() {
+        let headers = |block: &[u8]| { parse_headers(block, ParseOptions::default()).unwrap().1 };
+        let partial = headers(b"Status: 206\r\nContent-Range: bytes 100-199/200\r\n\r\n");
+        assert!(check_resumed(100, Some(206), &partial).is_ok());
+        let error = check_resumed(50, Some(206), &partial).unwrap_err().to_string();
+        assert_eq!(error, "Server returned a range starting at byte 100, but 50 was requested.");
+        let error = check_resumed(100, Some(200), &headers(b"Content-Type: text/plain\r\n\r\n")).unwrap_err().to_string();
+        assert!(error.starts_with("Server did not honor the range request (status: 200)"), "{}", error);
+    }
+
+    #[test]
+    fn maps_status_to_exit_code() {
+        assert_eq!(status_exit_code(200), 0);
+        assert_eq!(status_exit_code(204), 0);
+        assert_eq!(status_exit_code(301), 3);
+        assert_eq!(status_exit_code(404), 4);
+        assert_eq!(status_exit_code(503), 5);
+        assert_eq!(status_exit_code(101), 1);
+        assert_eq!(status_exit_code(600), 1);
+    }
+
+    #[test]
+    fn parses_params() {
+        assert_eq!(parse_param("A=b=c"), Ok(("A".to_string(), "b=c".to_string())));
+        assert!(parse_param("A").is_err());
+        assert!(parse_param("A B=c").is_err());
+    }
+
+    #[test]
+    fn sets_host_and_port_from_url() {
+        for (url, host, port, https) in [
+            ("http://example.com/", "example.com", "80", None),
+            ("https://example.com/", "example.com", "443", Some("on")),
+            ("http://example.com:8080/", "example.com:8080", "8080", None),
+            ("https://example.com:8443/", "example.com:8443", "8443", Some("on")),
+        ] {
+            let mut cli = parse_cli(&[]);
+            cli.url = Some(Url::parse(url).unwrap());
+            let params = Params::default().set_from_cli(&cli);
+            assert_eq!(param(&params, "HTTP_HOST"), Some(host), "{}", url);
+            assert_eq!(param(&params, "SERVER_NAME"), Some("example.com"), "{}", url);
+            assert_eq!(param(&params, "SERVER_PORT"), Some(port), "{}", url);
+            assert_eq!(param(&params, "HTTPS"), https, "{}", url);
+        }
+    }
+
+    #[test]
+    fn sets_remote_and_server_addr() {
+        let params = Params::default().set_from_cli(&parse_cli(&[]));
+        assert_eq!(param(&params, "REMOTE_ADDR"), Some(DEFAULT_REMOTE_ADDR));
+        assert_eq!(param(&params, "SERVER_ADDR"), None);
+
+        let mut params = Params::default();
+        params.insert("REMOTE_ADDR".into(), "192.0.2.1".into());
+        let params = params.set_from_cli(&parse_cli(&[]));
+        assert_eq!(param(&params, "REMOTE_ADDR"), Some("192.0.2.1"));
+
+        let params = Params::default().set_from_cli(&parse_cli(&["--remote-addr", "10.0.0.1", "--server-addr", "::1"]));
+        assert_eq!(param(&params, "REMOTE_ADDR"), Some("10.0.0.1"));
+        assert_eq!(param(&params, "SERVER_ADDR"), Some("::1"));
+        let params = Params::default().set_from_cli(&parse_cli(&["--remote-addr", "2001:db8::1"]));
+        assert_eq!(param(&params, "REMOTE_ADDR"), Some("2001:db8::1"));
+
+        assert!(Cli::try_parse_from(["fcgi", "--remote-addr", "localhost"]).is_err());
+    }
+
+    #[test]
+    fn strips_script_name_prefix() {
+        assert_eq!(strip_script_name("/app.php/info", "/app.php"), Some("/info"));
+        assert_eq!(strip_script_name("/app.php", "/app.php"), Some(""));
+        assert_eq!(strip_script_name("/app/info", "/app/"), Some("info"));
+        assert_eq!(strip_script_name("/app.phpx/info", "/app.php"), None);
+        assert_eq!(strip_script_name("/other.php/info", "/app.php"), None);
+    }
+
+    #[test]
+    fn checks_script_name() {
+        let cli = |args: &[&str]| {
+            let mut cli = parse_cli(args);
+            cli.url = Some(Url::parse("http://example.com/app.php/info").unwrap());
+            cli
+        };
+        assert!(cli(&["--script", "/app.php"]).check_script_name().is_ok());
+        // Only a warning, unless --strict
+        assert!(cli(&["--script", "/other.php"]).check_script_name().is_ok());
+        assert!(cli(&["--strict", "--script", "/other.php"]).check_script_name().is_err());
+
+        let params = Params::default().set_from_cli(&cli(&["--script", "/app.php"]));
+        assert_eq!(param(&params, "SCRIPT_NAME"), Some("/app.php"));
+        assert_eq!(param(&params, "PATH_INFO"), Some("/info"));
+    }
+
+    #[test]
+    fn sets_host_for_ip_literals() {
+        for (url, host, server_name, port) in [
+            ("http://127.0.0.1:8080/", "127.0.0.1:8080", "127.0.0.1", "8080"),
+            ("https://192.0.2.1/", "192.0.2.1", "192.0.2.1", "443"),
+            ("http://[::1]/", "[::1]", "[::1]", "80"),
+            ("http://[::1]:8080/", "[::1]:8080", "[::1]", "8080"),
+        ] {
+            let mut cli = parse_cli(&[]);
+            cli.url = Some(Url::parse(url).unwrap());
+            let params = Params::default().set_from_cli(&cli);
+            assert_eq!(param(&params, "HTTP_HOST"), Some(host), "{}", url);
+            assert_eq!(param(&params, "SERVER_NAME"), Some(server_name), "{}", url);
+            assert_eq!(param(&params, "SERVER_PORT"), Some(port), "{}", url);
+        }
+    }
+
+    #[test]
+    fn merges_query() {
+        for (url, args, query, request_uri) in [
+            ("/search?lang=en", &["--query", "q=a b"][..], Some("lang=en&q=a%20b"), "/search?lang=en&q=a%20b"),
+            ("/search", &["--query", "q=a&b", "--query", "x"][..], Some("q=a%26b&x"), "/search?q=a%26b&x"),
+            ("/search?lang=en", &[][..], Some("lang=en"), "/search?lang=en"),
+            ("/search", &[][..], None, "/search"),
+        ] {
+            let mut cli = parse_cli(args);
+            cli.url = Some(Url::parse("http://example.com/").unwrap().join(url).unwrap());
+            let params = Params::default().set_from_cli(&cli);
+            assert_eq!(param(&params, "QUERY_STRING"), query, "{} {:?}", url, args);
+            assert_eq!(param(&params, "REQUEST_URI"), Some(request_uri), "{} {:?}", url, args);
+        }
+    }
+
+    #[test]
+    fn guesses_data_file_content_type() {
+        let content_type = |args: &[&str]| {
+            let params = Params::default().set_from_cli(&parse_cli(args));
+            param(&params, "CONTENT_TYPE").map(str::to_string)
+        };
+        assert_eq!(content_type(&["--data-file", "body.json"]).as_deref(), Some("application/json"));
+        assert_eq!(content_type(&["--data-file", "body.bin"]).as_deref(), Some("application/octet-stream"));
+        assert_eq!(content_type(&["--data-file", "body.json", "--no-content-type-guess"]), None);
+        assert_eq!(content_type(&["--data-file", "-"]), None);
+        assert_eq!(
+            content_type(&["--data-file", "body.json", "-H", "Content-Type: text/plain"]).as_deref(),
+            Some("text/plain")
+        );
+        assert_eq!(
+            content_type(&["--data-file", "body.json", "--param", "CONTENT_TYPE=text/csv"]).as_deref(),
+            Some("text/csv")
+        );
+    }
+
+    #[test]
+    fn parses_methods() {
+        assert_eq!(parse_method("post"), Ok("POST".to_string()));
+        assert_eq!(parse_method("PropFind"), Ok("PROPFIND".to_string()));
+        assert!(parse_method("GE T").is_err());
+        assert!(parse_method("").is_err());
+
+        assert_eq!(parse_cli(&["-X", "head"]).method(), "HEAD");
+        assert!(parse_cli(&["-X", "head"]).is_get_like());
+        assert_eq!(parse_cli(&["-X", "post", "--head"]).method(), "HEAD");
+        assert!(parse_cli(&["-X", "propfind"]).check_method().is_ok());
+        assert!(parse_cli(&["-X", "brew"]).check_method().is_err());
+        assert!(parse_cli(&["-X", "brew", "--allow-custom-method"]).check_method().is_ok());
+    }
+
+    #[test]
+    fn sets_accept_encoding() {
+        let accept_encoding = |args: &[&str]| {
+            let params = Params::default().set_from_cli(&parse_cli(args));
+            param(&params, "HTTP_ACCEPT_ENCODING").map(str::to_string)
+        };
+        assert_eq!(accept_encoding(&[]).as_deref(), Some(DEFAULT_ACCEPT_ENCODING));
+        assert_eq!(accept_encoding(&["--no-accept-encoding"]), None);
+        assert_eq!(accept_encoding(&["-C", "100"]), None);
+        assert_eq!(accept_encoding(&["-H", "Accept-Encoding: br"]).as_deref(), Some("br"));
+
+        assert!(parse_cli(&[]).decompress());
+        assert!(!parse_cli(&["--no-accept-encoding"]).decompress());
+    }
+
+    #[test]
+    fn splits_path_info() {
+        let params = |regex: &str, path: &str| {
+            let mut cli = parse_cli(&["--split-path-info", regex, "--root", "/srv"]);
+            cli.url = Some(Url::parse("http://example.com/").unwrap().join(path).unwrap());
+            let params = Params::default().set_from_cli(&cli);
+            ["SCRIPT_NAME", "PATH_INFO", "SCRIPT_FILENAME"].map(|name| { param(&params, name).map(str::to_string) })
+        };
+        let some = |values: [&str; 3]| { values.map(|v| { Some(v.to_string()) }) };
+
+        assert_eq!(
+            params(r"^(.+\.php)(/.+)$", "/index.php/foo/bar"),
+            some(["/index.php", "/foo/bar", "/srv/index.php"])
+        );
+        assert_eq!(params(r"^(.+\.php)(/.+)$", "/a.php/b.php/c"), some(["/a.php/b.php", "/c", "/srv/a.php/b.php"]));
+        assert_eq!(params(r"^(.+?\.php)(/.+)$", "/a.php/b.php/c"), some(["/a.php", "/b.php/c", "/srv/a.php"]));
+        // An empty PATH_INFO is not sent
+        assert_eq!(
+            params(r"^(.+\.php)(/.*)?$", "/index.php"),
+            [Some("/index.php".to_string()), None, Some("/srv/index.php".to_string())]
+        );
+        // Without a match, the entire path is PATH_INFO, as without the option
+        assert_eq!(params(r"^(.+\.php)(/.+)$", "/index.php"), [None, Some("/index.php".to_string()), None]);
+
+        assert!(parse_split_path_info(r"^(.+\.php)$").is_err());
+        assert!(parse_split_path_info(r"^(.+\.php(/.+)$").is_err());
+    }
+
+    #[test]
+    fn sets_basic_auth() {
+        let params = Params::default().set_basic_auth("aladdin", "opensesame");
+        assert_eq!(param(&params, "AUTH_TYPE"), Some("Basic"));
+        assert_eq!(param(&params, "REMOTE_USER"), Some("aladdin"));
+        assert_eq!(param(&params, "HTTP_AUTHORIZATION"), Some("Basic YWxhZGRpbjpvcGVuc2VzYW1l"));
+    }
+
+    #[test]
+    fn decodes_path_info() {
+        let mut cli = parse_cli(&["--script", "/app.php", "--root", "/srv/"]);
+        cli.url = Some(Url::parse("http://example.com/app.php/caf%C3%A9%20au%20lait?x=1").unwrap());
+        let params = Params::default().set_from_cli(&cli);
+        assert_eq!(param(&params, "PATH_INFO"), Some("/café au lait"));
+        assert_eq!(param(&params, "PATH_TRANSLATED"), Some("/srv/café au lait"));
+        assert_eq!(param(&params, "SCRIPT_FILENAME"), Some("/srv/app.php"));
+        assert_eq!(param(&params, "REQUEST_URI"), Some("/app.php/caf%C3%A9%20au%20lait?x=1"));
+        assert_eq!(param(&params, "QUERY_STRING"), Some("x=1"));
+    }
+
+    #[test]
+    fn looks_up_reason_phrases() {
+        assert_eq!(reason_phrase(200), Some("OK"));
+        assert_eq!(reason_phrase(404), Some("Not Found"));
+        assert_eq!(reason_phrase(299), None);
+    }
+
+    #[test]
+    fn renames_environment_variables() {
+        let cli = parse_cli(&["-e", "DB_URL=DATABASE_URL"]);
+        assert_eq!(cli.env_param_names("DB_URL"), ["DATABASE_URL"]);
+        let cli = parse_cli(&["-e", "DB_URL=DATABASE_URL", "-e", "DB_URL"]);
+        assert_eq!(cli.env_param_names("DB_URL"), ["DATABASE_URL", "DB_URL"]);
+    }
+
+    #[test]
+    fn whitelists_environment_variables() {
+        let cli = parse_cli(&["-e", "MYAPP_*", "-e", "TZ"]);
+        assert!(cli.is_envvar_whitelisted("MYAPP_DB"));
+        assert!(cli.is_envvar_whitelisted("MYAPP_"));
+        assert!(cli.is_envvar_whitelisted("TZ"));
+        assert!(cli.is_envvar_whitelisted("HTTP_ACCEPT"));
+        assert!(cli.is_envvar_whitelisted("QUERY_STRING"));
+        assert!(!cli.is_envvar_whitelisted("MYAPPDB"));
+        assert!(!cli.is_envvar_whitelisted("HOME"));
+
+        let cli = parse_cli(&["--no-env", "-e", "MYAPP_*"]);
+        assert!(cli.is_envvar_whitelisted("MYAPP_DB"));
+        assert!(!cli.is_envvar_whitelisted("HTTP_ACCEPT"));
+        assert!(!cli.is_envvar_whitelisted("QUERY_STRING"));
+
+        assert!(parse_cli(&["-E"]).is_envvar_whitelisted("HOME"));
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("2k"), Ok(2048));
+        assert_eq!(parse_size("1M"), Ok(1 << 20));
+        assert!(parse_size("1T").is_err());
+        assert!(parse_size("").is_err());
+    }
+
+    #[test]
+    fn sanitizes_file_names() {
+        assert_eq!(sanitize_file_name("report.pdf").as_deref(), Some("report.pdf"));
+        assert_eq!(sanitize_file_name("my%20file.txt").as_deref(), Some("my file.txt"));
+        assert_eq!(sanitize_file_name("..%2F..%2Fetc%2Fpasswd").as_deref(), Some("....etcpasswd"));
+        assert_eq!(sanitize_file_name("a%5Cb").as_deref(), Some("ab"));
+        assert_eq!(sanitize_file_name(""), None);
+        assert_eq!(sanitize_file_name(".."), None);
+        assert_eq!(sanitize_file_name("%2F"), None);
+    }
+}
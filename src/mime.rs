@@ -0,0 +1,51 @@
+use std::path::Path;
+
+/// Guess the media type of a file from its extension
+///
+/// Only covers common types. Anything else is application/octet-stream.
+pub fn guess_content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|e| { e.to_string_lossy().to_ascii_lowercase() })
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "html" | "htm" => "text/html",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "yaml" | "yml" => "application/yaml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guesses_from_extension() {
+        assert_eq!(guess_content_type(Path::new("data.json")), "application/json");
+        assert_eq!(guess_content_type(Path::new("/srv/feed.XML")), "application/xml");
+        assert_eq!(guess_content_type(Path::new("logo.png")), "image/png");
+        assert_eq!(guess_content_type(Path::new("photo.jpeg")), "image/jpeg");
+    }
+
+    #[test]
+    fn falls_back_to_octet_stream() {
+        assert_eq!(guess_content_type(Path::new("archive.tar.xz")), "application/octet-stream");
+        assert_eq!(guess_content_type(Path::new("README")), "application/octet-stream");
+        assert_eq!(guess_content_type(Path::new(".json")), "application/octet-stream");
+    }
+}
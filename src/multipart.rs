@@ -0,0 +1,197 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::Cursor,
+    path::PathBuf,
+    pin::Pin
+};
+use tokio::{
+    fs::File,
+    io::{self, AsyncRead, AsyncReadExt}
+};
+
+/// Content of a form part
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartSource {
+    Text(String),
+    File(PathBuf),
+}
+
+/// Form part given with -F, --form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormPart {
+    pub name: String,
+    pub source: PartSource,
+    pub content_type: Option<String>,
+    pub filename: Option<String>,
+}
+
+/// Parse NAME=VALUE or NAME=@PATH, optionally followed by ;type=TYPE and/or
+/// ;filename=NAME
+pub fn parse_form_part(s: &str) -> Result<FormPart, String> {
+    let (name, rest) = s
+        .split_once('=')
+        .ok_or_else(|| { format!("Malformed form field \"{}\", expected \"NAME=VALUE\"", s) })?;
+
+    let mut segments = rest.split(';');
+    let mut value = segments.next().unwrap_or_default().to_string();
+    let mut content_type = None;
+    let mut filename = None;
+
+    for segment in segments {
+        if let Some(t) = segment.strip_prefix("type=") {
+            content_type = Some(t.to_string());
+        } else if let Some(f) = segment.strip_prefix("filename=") {
+            filename = Some(f.to_string());
+        } else {
+            // Not an option, so it must have been part of the value
+            value.push(';');
+            value.push_str(segment);
+        }
+    }
+
+    let source = match value.strip_prefix('@') {
+        Some(path) => PartSource::File(PathBuf::from(path)),
+        None => PartSource::Text(value),
+    };
+
+    Ok(FormPart { name: name.to_string(), source, content_type, filename })
+}
+
+/// A multipart/form-data request body
+pub struct Multipart {
+    boundary: String,
+    parts: Vec<FormPart>,
+}
+
+impl Multipart {
+    pub fn new(parts: Vec<FormPart>) -> Self {
+        let random = RandomState::new().build_hasher().finish();
+        Self {
+            boundary: format!("------------------------{:016x}", random),
+            parts,
+        }
+    }
+
+    /// Value for the CONTENT_TYPE parameter
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Open the body for reading, returning its length and a reader
+    ///
+    /// Files are opened right away, but only read as the body is read.
+    pub async fn open(self) -> io::Result<(u64, Pin<Box<dyn AsyncRead + Send>>)> {
+        let mut length = 0u64;
+        let mut reader: Pin<Box<dyn AsyncRead + Send>> = Box::pin(io::empty());
+
+        for part in self.parts.iter() {
+            let mut head = format!("--{}\r\n", self.boundary);
+            head += &format!("Content-Disposition: form-data; name=\"{}\"", escape_quoted(&part.name));
+
+            let filename = part.filename.clone().or_else(|| {
+                match &part.source {
+                    PartSource::File(path) => path.file_name().map(|f| { f.to_string_lossy().into_owned() }),
+                    PartSource::Text(_) => None,
+                }
+            });
+            if let Some(filename) = filename {
+                head += &format!("; filename=\"{}\"", escape_quoted(&filename));
+            }
+            head += "\r\n";
+
+            let content_type = part.content_type.as_deref().or(match part.source {
+                PartSource::File(_) => Some("application/octet-stream"),
+                PartSource::Text(_) => None,
+            });
+            if let Some(content_type) = content_type {
+                head += &format!("Content-Type: {}\r\n", content_type);
+            }
+            head += "\r\n";
+
+            length += head.len() as u64 + 2;
+            reader = Box::pin(reader.chain(Cursor::new(head.into_bytes())));
+
+            match &part.source {
+                PartSource::Text(text) => {
+                    length += text.len() as u64;
+                    reader = Box::pin(reader.chain(Cursor::new(text.clone().into_bytes())));
+                }
+                PartSource::File(path) => {
+                    let file = File::open(path).await.map_err(|e| {
+                        io::Error::new(e.kind(), format!("Failed to open {}: {}", path.display(), e))
+                    })?;
+                    length += file.metadata().await?.len();
+                    reader = Box::pin(reader.chain(file));
+                }
+            }
+
+            reader = Box::pin(reader.chain(&b"\r\n"[..]));
+        }
+
+        let tail = format!("--{}--\r\n", self.boundary);
+        length += tail.len() as u64;
+        reader = Box::pin(reader.chain(Cursor::new(tail.into_bytes())));
+
+        Ok((length, reader))
+    }
+}
+
+fn escape_quoted(s: &str) -> String {
+    s.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_form_parts() {
+        let part = parse_form_part("file=@photo.jpg;type=image/jpeg;filename=me.jpg").unwrap();
+        assert_eq!(part.name, "file");
+        assert_eq!(part.source, PartSource::File(PathBuf::from("photo.jpg")));
+        assert_eq!(part.content_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(part.filename.as_deref(), Some("me.jpg"));
+
+        let part = parse_form_part("q=a;b").unwrap();
+        assert_eq!(part.source, PartSource::Text("a;b".to_string()));
+        assert!(parse_form_part("novalue").is_err());
+    }
+
+    #[tokio::test]
+    async fn encodes_body() {
+        let path = std::env::temp_dir().join(format!("fcgi-multipart-{}.txt", std::process::id()));
+        std::fs::write(&path, "file contents").unwrap();
+        let parts = vec![
+            parse_form_part("name=\"quoted\"").unwrap(),
+            parse_form_part(&format!("upload=@{};filename=a.txt", path.display())).unwrap(),
+        ];
+        let multipart = Multipart { boundary: "XYZ".to_string(), parts };
+        assert_eq!(multipart.content_type(), "multipart/form-data; boundary=XYZ");
+
+        let (length, mut reader) = multipart.open().await.unwrap();
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = concat!(
+            "--XYZ\r\n",
+            "Content-Disposition: form-data; name=\"name\"\r\n",
+            "\r\n",
+            "\"quoted\"\r\n",
+            "--XYZ\r\n",
+            "Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n",
+            "Content-Type: application/octet-stream\r\n",
+            "\r\n",
+            "file contents\r\n",
+            "--XYZ--\r\n"
+        );
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+        assert_eq!(length, expected.len() as u64);
+    }
+
+    #[test]
+    fn escapes_quoted_strings() {
+        assert_eq!(escape_quoted("a\"b\r\nc"), "a%22b%0D%0Ac");
+    }
+}
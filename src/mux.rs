@@ -0,0 +1,260 @@
+use crate::{
+    fcgi::{RecordHeader, RecordType, HEADER_LEN, KEEP_CONN, REQUEST_ID},
+    transport::Stream
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf},
+    sync::{mpsc, Mutex},
+    task
+};
+use tokio_util::sync::CancellationToken;
+
+/// Buffer size of the virtual stream of each request
+const STREAM_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Records on their way to the client of a request, by request id
+type Routes = Rc<RefCell<HashMap<u16, mpsc::UnboundedSender<Vec<u8>>>>>;
+
+/// A single connection shared by concurrent requests
+///
+/// Each request gets a virtual stream of its own, which it may use as if it
+/// were a dedicated connection, using the default request id. The records
+/// written to it are assigned a request id unique on the connection, and
+/// FCGI_KEEP_CONN is set on the request, so the application does not close
+/// the connection. Records received for that id are passed back with the
+/// default request id. Records are queued for each request, so a client
+/// not reading its stream does not hold up the others. The queue is not
+/// bounded, so it grows for as long as the client does not read.
+///
+/// The connection is driven by local tasks, so this must be used within a
+/// `LocalSet`.
+pub struct Multiplexer {
+    writer: Rc<Mutex<WriteHalf<Box<dyn Stream>>>>,
+    routes: Routes,
+    next_id: Cell<u16>,
+}
+
+impl Multiplexer {
+    pub fn new(stream: Box<dyn Stream>) -> Self {
+        let (reader, writer) = io::split(stream);
+        let routes = Rc::new(RefCell::new(HashMap::new()));
+        task::spawn_local(route_responses(reader, routes.clone()));
+
+        Self {
+            writer: Rc::new(Mutex::new(writer)),
+            routes,
+            next_id: Cell::new(REQUEST_ID),
+        }
+    }
+
+    /// Open a virtual stream for a new request
+    ///
+    /// Fails if every request id is taken by a request still in progress.
+    pub fn open(&self) -> io::Result<Box<dyn Stream>> {
+        let id = self.take_id().ok_or_else(|| {
+            io::Error::other("No request id left on the shared connection")
+        })?;
+
+        let (client_end, mux_end) = io::duplex(STREAM_BUFFER_SIZE);
+        let (from_client, to_client) = io::split(mux_end);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let ended = CancellationToken::new();
+        self.routes.borrow_mut().insert(id, sender);
+        task::spawn_local(forward_requests(from_client, self.writer.clone(), id, ended.clone()));
+        task::spawn_local(forward_responses(receiver, to_client, ended));
+
+        Ok(Box::new(client_end))
+    }
+
+    /// Next request id not in use by a request in progress, wrapping around
+    /// after the highest one
+    fn take_id(&self) -> Option<u16> {
+        let routes = self.routes.borrow();
+        let mut id = self.next_id.get();
+        for _ in REQUEST_ID..=u16::MAX {
+            let next = id.checked_add(1).unwrap_or(REQUEST_ID);
+            if !routes.contains_key(&id) {
+                self.next_id.set(next);
+                return Some(id);
+            }
+            id = next;
+        }
+        None
+    }
+}
+
+async fn read_record<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(RecordHeader, Vec<u8>)> {
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header).await?;
+    let header = RecordHeader::from_bytes(header);
+
+    let mut content = vec![0u8; header.content_length as usize + header.padding_length as usize];
+    reader.read_exact(&mut content).await?;
+    Ok((header, content))
+}
+
+/// Pass the records written by a client on to the connection, under the
+/// request id assigned to it, until `ended` is cancelled.
+///
+/// Only waiting for the next record is cancelled, as a record written in
+/// part would corrupt the connection for all other requests.
+async fn forward_requests(
+    mut from_client: ReadHalf<DuplexStream>,
+    writer: Rc<Mutex<WriteHalf<Box<dyn Stream>>>>,
+    id: u16,
+    ended: CancellationToken
+) -> io::Result<()> {
+    loop {
+        let (mut header, mut content) = tokio::select! {
+            record = read_record(&mut from_client) => record?,
+            _ = ended.cancelled() => return Ok(()),
+        };
+        header.request_id = id;
+        if header.record_type == RecordType::BeginRequest as u8 && content.len() > 2 {
+            content[2] |= KEEP_CONN;
+        }
+
+        let mut writer = writer.lock().await;
+        writer.write_all(&header.to_bytes()).await?;
+        writer.write_all(&content).await?;
+        writer.flush().await?;
+    }
+}
+
+/// Pass the records received on the connection on to the client each of
+/// them is meant for. Once the connection is closed, so are all virtual
+/// streams.
+async fn route_responses(mut reader: ReadHalf<Box<dyn Stream>>, routes: Routes) {
+    while let Ok((mut header, content)) = read_record(&mut reader).await {
+        let id = header.request_id;
+        let mut routes = routes.borrow_mut();
+        let Some(sender) = routes.get(&id) else {
+            // Management record, or a request that is gone already
+            continue;
+        };
+
+        header.request_id = REQUEST_ID;
+        let mut record = header.to_bytes().to_vec();
+        record.extend_from_slice(&content);
+        // The client may have gone already, but the id stays in use until
+        // the application ends the request
+        let _ = sender.send(record);
+
+        if header.record_type == RecordType::EndRequest as u8 {
+            routes.remove(&id);
+        }
+    }
+
+    routes.borrow_mut().clear();
+}
+
+/// Write the records queued for a client to its virtual stream. Once the
+/// request has ended or the connection is closed, the stream is closed, and
+/// `ended` is cancelled to stop forwarding requests.
+async fn forward_responses(
+    mut receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    mut to_client: WriteHalf<DuplexStream>,
+    ended: CancellationToken
+) {
+    while let Some(record) = receiver.recv().await {
+        if to_client.write_all(&record).await.is_err() {
+            break;
+        }
+    }
+    let _ = to_client.shutdown().await;
+    ended.cancel();
+}
+
+/// Whether the values returned for FCGI_GET_VALUES indicate support for
+/// multiplexing
+pub fn supports_multiplexing(values: &[(String, String)]) -> bool {
+    values.iter().any(|(name, value)| { name == "FCGI_MPXS_CONNS" && value == "1" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::{task::LocalSet, time};
+
+    fn record(record_type: RecordType, request_id: u16, content: &[u8]) -> Vec<u8> {
+        let header = RecordHeader {
+            record_type: record_type as u8,
+            request_id,
+            content_length: content.len() as u16,
+            padding_length: 0,
+        };
+        let mut record = header.to_bytes().to_vec();
+        record.extend_from_slice(content);
+        record
+    }
+
+    #[tokio::test]
+    async fn skips_ids_in_use() {
+        LocalSet::new().run_until(async {
+            let (connection, _application) = io::duplex(1024);
+            let mux = Multiplexer::new(Box::new(connection));
+            let _first = mux.open().unwrap();
+            mux.next_id.set(u16::MAX);
+            let _last = mux.open().unwrap();
+            // Wraps around, but id 1 is still in use
+            let _next = mux.open().unwrap();
+
+            let mut ids: Vec<_> = mux.routes.borrow().keys().copied().collect();
+            ids.sort();
+            assert_eq!(ids, [1, 2, u16::MAX]);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn routes_past_a_client_not_reading() {
+        LocalSet::new().run_until(async {
+            let (connection, mut application) = io::duplex(1024);
+            let mux = Multiplexer::new(Box::new(connection));
+            let _stalled = mux.open().unwrap();
+            let mut client = mux.open().unwrap();
+
+            task::spawn_local(async move {
+                // More than fits in the stream of the first request
+                let content = vec![0; 60000];
+                for _ in 0..8 {
+                    application.write_all(&record(RecordType::Stdout, 1, &content)).await.unwrap();
+                }
+                application.write_all(&record(RecordType::Stdout, 2, b"ok")).await.unwrap();
+            });
+
+            let (header, content) = time::timeout(Duration::from_secs(5), read_record(&mut client))
+                .await
+                .expect("response held up by the other request")
+                .unwrap();
+            assert_eq!(header.request_id, REQUEST_ID);
+            assert_eq!(content, b"ok");
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn closes_streams_with_the_connection() {
+        LocalSet::new().run_until(async {
+            let (connection, mut application) = io::duplex(1024);
+            let mux = Multiplexer::new(Box::new(connection));
+            let mut client = mux.open().unwrap();
+
+            application.write_all(&record(RecordType::Stdout, 1, b"partial")).await.unwrap();
+            drop(application);
+
+            let (_, content) = read_record(&mut client).await.unwrap();
+            assert_eq!(content, b"partial");
+            let mut rest = Vec::new();
+            let read = time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest))
+                .await
+                .expect("stream left open after the connection closed");
+            assert!(matches!(read, Ok(0) | Err(_)));
+            assert!(mux.routes.borrow().is_empty());
+        }).await;
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll}
+};
+use tokio::io::{self, AsyncWrite};
+
+/// Writer prepending `prefix` to every line written to `inner`, for
+/// --stderr-prefix
+///
+/// Output is buffered until written to `inner` by the next write, flush or
+/// shutdown, so a partial line is not split from its prefix.
+pub struct LinePrefixWriter<W> {
+    inner: W,
+    prefix: Vec<u8>,
+    at_line_start: bool,
+    /// Prefixed output not yet written to `inner`
+    pending: Vec<u8>,
+}
+
+impl<W> LinePrefixWriter<W> {
+    pub fn new(inner: W, prefix: impl Into<Vec<u8>>) -> Self {
+        Self { inner, prefix: prefix.into(), at_line_start: true, pending: Vec::new() }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> LinePrefixWriter<W> {
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for LinePrefixWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+
+        for line in buf.split_inclusive(|&b| { b == b'\n' }) {
+            if this.at_line_start {
+                this.pending.extend_from_slice(&this.prefix);
+            }
+            this.pending.extend_from_slice(line);
+            this.at_line_start = line.ends_with(b"\n");
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    async fn prefixed(prefix: &str, writes: &[&str]) -> String {
+        let mut writer = LinePrefixWriter::new(Vec::new(), prefix);
+        for data in writes {
+            writer.write_all(data.as_bytes()).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        String::from_utf8(writer.inner).unwrap()
+    }
+
+    #[tokio::test]
+    async fn prefixes_each_line() {
+        assert_eq!(prefixed("[app] ", &["one\ntwo\n\nthree"]).await, "[app] one\n[app] two\n[app] \n[app] three");
+    }
+
+    #[tokio::test]
+    async fn continues_partial_line() {
+        assert_eq!(prefixed("[app] ", &["on", "e\ntw", "", "o\n"]).await, "[app] one\n[app] two\n");
+    }
+
+    #[tokio::test]
+    async fn passes_through_without_prefix() {
+        assert_eq!(prefixed("", &["one\n", "two"]).await, "one\ntwo");
+    }
+}
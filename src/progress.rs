@@ -0,0 +1,117 @@
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant}
+};
+use tokio::io::{self, AsyncRead, ReadBuf};
+
+/// Minimum time between two updates
+const REPORT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Counter of bytes transferred, reported on stderr for --progress
+pub struct Progress {
+    /// Verb describing the transfer, e.g. "Uploaded"
+    label: &'static str,
+    total: Option<u64>,
+    done: u64,
+    last_report: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(label: &'static str, total: Option<u64>) -> Self {
+        Self { label, total, done: 0, last_report: None }
+    }
+
+    /// Count `n` more bytes, reporting if the last report is long enough ago
+    pub fn advance(&mut self, n: u64) {
+        self.done += n;
+        if self.last_report.is_none_or(|t| { t.elapsed() >= REPORT_INTERVAL }) {
+            self.report();
+        }
+    }
+
+    /// Report the final count, if anything was transferred at all
+    pub fn finish(&mut self) {
+        if self.done > 0 || self.last_report.is_some() {
+            self.report();
+            eprintln!();
+            self.last_report = None;
+            self.done = 0;
+        }
+    }
+
+    fn report(&mut self) {
+        match self.total.filter(|&total| { total > 0 }) {
+            Some(total) => eprint!(
+                "\r{} {} of {} bytes ({}%)",
+                self.label,
+                self.done,
+                total,
+                self.done.saturating_mul(100) / total
+            ),
+            None => eprint!("\r{} {} bytes", self.label, self.done),
+        }
+        self.last_report = Some(Instant::now());
+    }
+}
+
+/// Reader reporting the progress of reading from `inner`
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, progress: Progress) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+
+        let n = buf.filled().len() - before;
+        if n > 0 {
+            self.progress.advance(n as u64);
+        } else if buf.remaining() > 0 {
+            // End of input
+            self.progress.finish();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn counts_bytes_read() {
+        let mut reader = ProgressReader::new(&b"0123456789"[..], Progress::new("Uploaded", Some(10)));
+        let mut buf = [0; 4];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 4);
+        assert_eq!(reader.progress.done, 4);
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 4);
+        assert_eq!(reader.progress.done, 8);
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"89");
+        // Reset once the end of input is reported
+        assert_eq!(reader.progress.done, 0);
+        assert!(reader.progress.last_report.is_none());
+    }
+
+    #[test]
+    fn does_not_report_empty_transfer() {
+        let mut progress = Progress::new("Downloaded", None);
+        progress.finish();
+        assert!(progress.last_report.is_none());
+        progress.advance(3);
+        assert_eq!(progress.done, 3);
+        assert!(progress.last_report.is_some());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{headers::Headers, write_out::Stats};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+use std::time::Duration;
+use url::Url;
+
+/// What to write to stdout for a response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// The response body, as sent by the application
+    #[default]
+    Body,
+    /// A JSON object describing the response
+    Json,
+}
+
+/// Format of the headers written by -D, --dump-header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HeaderDumpFormat {
+    /// The header block, as sent by the application
+    #[default]
+    Raw,
+    /// A JSON array of name/value objects
+    Json,
+}
+
+/// Headers as name/value objects in order of appearance, with names as
+/// received
+pub fn headers_json(headers: &Headers) -> Value {
+    headers
+        .iter()
+        .map(|(name, value)| { json!({ "name": name, "value": value }) })
+        .collect()
+}
+
+/// Describe a completed request, for --output-format json
+///
+/// Headers are listed as name/value objects in order of appearance, so
+/// repeated fields are preserved. Names are given as received. The body is
+/// included base64-encoded, if it was captured rather than written to a file.
+pub fn json_report(url: Option<&Url>, stats: &Stats) -> Value {
+    let seconds = |d: Option<Duration>| { d.map(|d| { d.as_secs_f64() }) };
+    let mut report = json!({
+        "url": url.map(Url::as_str),
+        "status": stats.response_code,
+        "headers": headers_json(&stats.headers),
+        "body_length": stats.size_download,
+        "stderr_length": stats.size_stderr,
+        "app_status": stats.app_status,
+        "num_redirects": stats.num_redirects,
+        "timings": {
+            "connect": seconds(stats.time_connect),
+            "starttransfer": seconds(stats.time_starttransfer),
+            "total": seconds(stats.time_total),
+        },
+    });
+    if let Some(body) = stats.body.as_ref() {
+        report["body"] = json!(STANDARD.encode(body));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::{parse_headers, ParseOptions};
+
+    #[test]
+    fn reports_response() {
+        let mut stats = Stats::new();
+        stats.response_code = Some(201);
+        stats.headers = parse_headers(b"Set-Cookie: a=1\r\nset-cookie: b=2\r\n\r\n", ParseOptions::default()).unwrap().1;
+        stats.size_download = 2;
+        stats.size_stderr = 5;
+        stats.app_status = Some(3);
+        stats.num_redirects = 1;
+        stats.time_total = Some(Duration::from_millis(250));
+        stats.body = Some(b"ok".to_vec());
+        let url = Url::parse("http://localhost/new").unwrap();
+
+        assert_eq!(
+            json_report(Some(&url), &stats),
+            json!({
+                "url": "http://localhost/new",
+                "status": 201,
+                "headers": [{ "name": "Set-Cookie", "value": "a=1" }, { "name": "set-cookie", "value": "b=2" }],
+                "body_length": 2,
+                "stderr_length": 5,
+                "app_status": 3,
+                "num_redirects": 1,
+                "timings": { "connect": null, "starttransfer": null, "total": 0.25 },
+                "body": "b2s=",
+            })
+        );
+    }
+
+    #[test]
+    fn omits_body_not_captured() {
+        let report = json_report(None, &Stats::new());
+        assert_eq!(report["url"], Value::Null);
+        assert_eq!(report["status"], Value::Null);
+        assert_eq!(report["headers"], json!([]));
+        assert!(report.get("body").is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::{
+    error::FcgiCliError,
+    fcgi::{Client, Role},
+    headers::{parse_headers, Headers, ParseOptions},
+    response_status,
+    transport::Transport
+};
+use fastcgi_client::Params;
+use tokio::io;
+
+/// A single FastCGI request, to be sent with `send`
+///
+/// Unlike the command line interface, this derives nothing from a URL: all
+/// parameters besides GATEWAY_INTERFACE, SERVER_PROTOCOL, SERVER_SOFTWARE and
+/// CONTENT_LENGTH must be set explicitly.
+pub struct FcgiRequestBuilder {
+    transport: Transport,
+    params: Params<'static>,
+    body: Vec<u8>,
+    role: Role,
+    keep_conn: bool,
+}
+
+impl FcgiRequestBuilder {
+    pub fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            params: Params::default()
+                .gateway_interface("CGI/1.1")
+                .server_software(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+                .server_protocol("HTTP/1.1"),
+            body: Vec::new(),
+            role: Role::Responder,
+            keep_conn: false,
+        }
+    }
+
+    /// Set parameter `name` to `value`, replacing any earlier value
+    pub fn param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.insert(name.into().into(), value.into().into());
+        self
+    }
+
+    /// Send `body` on FCGI_STDIN. CONTENT_LENGTH is set to its length,
+    /// unless set explicitly.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Set FCGI_KEEP_CONN on the request
+    pub fn keep_conn(mut self, keep_conn: bool) -> Self {
+        self.keep_conn = keep_conn;
+        self
+    }
+
+    /// Connect to the application, send the request and read the response
+    /// in full
+    pub async fn send(mut self) -> Result<Response, FcgiCliError> {
+        if !self.body.is_empty() && self.params.get("CONTENT_LENGTH").is_none() {
+            self.params.insert("CONTENT_LENGTH".into(), self.body.len().to_string().into());
+        }
+
+        let stream = self.transport.connect(None).await.map_err(FcgiCliError::Connect)?;
+        let mut response = Client::new(stream)
+            .role(self.role)
+            .keep_conn(self.keep_conn)
+            .execute(&self.params, &mut self.body.as_slice(), &mut io::empty())
+            .await?;
+
+        let mut stdout = Vec::new();
+        while let Some(chunk) = response.next_stdout().await? {
+            stdout.extend(chunk);
+        }
+
+        let (body, headers) = parse_headers(&stdout, ParseOptions::default())
+            .map_err(|_| { FcgiCliError::HeaderParse("Malformed response header.".to_string()) })?;
+
+        Ok(Response {
+            status: response_status(&headers)?,
+            body: body.to_vec(),
+            headers,
+            stderr: response.stderr().to_vec(),
+            app_status: response.end_request().map(|end_request| { end_request.app_status }),
+            protocol_status: response.end_request().map(|end_request| { end_request.protocol_status }),
+        })
+    }
+}
+
+/// Response to a request sent by `FcgiRequestBuilder`
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// Status from the Status header, or 200 if there is none
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Output on FCGI_STDERR
+    pub stderr: Vec<u8>,
+    /// Application status from FCGI_END_REQUEST
+    pub app_status: Option<u32>,
+    /// Protocol status from FCGI_END_REQUEST, 0 if the request completed
+    /// normally
+    pub protocol_status: Option<u8>,
+}
@@ -0,0 +1,41 @@
+use std::{
+    cell::RefCell,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll}
+};
+use tokio::io::{self, AsyncWrite};
+
+/// Writer that can be cloned, all clones writing to the same `inner`
+///
+/// Used where the response body and FCGI_STDERR both go to stdout, so that
+/// they are written in the order they were received.
+pub struct SharedWriter<W> {
+    inner: Rc<RefCell<W>>,
+}
+
+impl<W> SharedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner: Rc::new(RefCell::new(inner)) }
+    }
+}
+
+impl<W> Clone for SharedWriter<W> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for SharedWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner.borrow_mut()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner.borrow_mut()).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner.borrow_mut()).poll_shutdown(cx)
+    }
+}
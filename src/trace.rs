@@ -0,0 +1,77 @@
+use crate::{
+    color::{ColorChoice, Colors},
+    fcgi::{Direction, RecordHeader, RecordType}
+};
+use std::{
+    fs::OpenOptions,
+    io::{self, IsTerminal, Write},
+    path::Path
+};
+
+/// Number of content bytes per line of a hex dump
+const BYTES_PER_LINE: usize = 16;
+
+/// Log of every record sent and received, written for --trace
+pub struct Trace {
+    out: Box<dyn Write>,
+    colors: Colors,
+}
+
+impl Trace {
+    /// Open the trace at `path`, or stderr if it is "-"
+    ///
+    /// Records are appended to an existing file, so the trace of a request
+    /// does not replace that of a previous one, e.g. when following a
+    /// redirect. Output is colorized as `color` says; a file is never
+    /// considered a terminal.
+    pub fn open(path: &Path, color: ColorChoice) -> io::Result<Self> {
+        let (out, is_terminal): (Box<dyn Write>, bool) = if path.as_os_str() == "-" {
+            (Box::new(io::stderr()), io::stderr().is_terminal())
+        } else {
+            (Box::new(OpenOptions::new().create(true).append(true).open(path)?), false)
+        };
+        Ok(Self { out, colors: Colors::new(color, is_terminal) })
+    }
+
+    /// Log a record, including a hex and ASCII dump of its content
+    ///
+    /// Write errors are ignored, as tracing should not break the request.
+    pub fn record(&mut self, direction: Direction, header: &RecordHeader, content: &[u8]) {
+        let _ = self.write_record(direction, header, content);
+    }
+
+    fn write_record(&mut self, direction: Direction, header: &RecordHeader, content: &[u8]) -> io::Result<()> {
+        let arrow = match direction {
+            Direction::Sent => "=> Send",
+            Direction::Received => "<= Recv",
+        };
+        let type_name = RecordType::from_u8(header.record_type).map_or("unknown type", RecordType::name);
+        writeln!(
+            self.out,
+            "{} {} ({}), request id {}, {} bytes",
+            self.colors.marker(arrow),
+            self.colors.name(type_name),
+            header.record_type,
+            header.request_id,
+            header.content_length
+        )?;
+
+        for (i, line) in content.chunks(BYTES_PER_LINE).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| { format!("{:02x}", b) }).collect();
+            let ascii: String = line
+                .iter()
+                .map(|&b| { if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' } })
+                .collect();
+            writeln!(
+                self.out,
+                "{}: {:<width$} {}",
+                self.colors.dim(&format!("{:04x}", i * BYTES_PER_LINE)),
+                hex.join(" "),
+                ascii,
+                width = BYTES_PER_LINE * 3 - 1
+            )?;
+        }
+
+        self.out.flush()
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    env,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    os::fd::{BorrowedFd, OwnedFd, RawFd},
+    path::PathBuf,
+    process
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite},
+    net::{self, TcpSocket, TcpStream, UnixStream}
+};
+
+/// Port assumed when forcing TCP on an address that lacks one
+pub const DEFAULT_TCP_PORT: u16 = 9000;
+
+/// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Size of `sun_path` in `struct sockaddr_un`, which bounds the length of a
+/// Unix socket path, including the terminating NUL
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SUN_PATH_LEN: usize = 108;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SUN_PATH_LEN: usize = 104;
+
+/// Any bidirectional byte stream a FastCGI client may run on
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// TCP connection to HOST:PORT, kept in a form accepted by `TcpStream::connect`
+    Tcp(String),
+    /// Connection to a Unix domain socket at the given path
+    Unix(PathBuf),
+    /// Connection to a Unix domain socket in the abstract namespace (Linux)
+    Abstract(String),
+    /// Already connected socket, inherited as the given file descriptor
+    Fd(RawFd),
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Tcp(address) => write!(f, "{}", address),
+            Transport::Unix(path) => write!(f, "{}", path.display()),
+            Transport::Abstract(name) => write!(f, "@{}", name),
+            Transport::Fd(fd) => write!(f, "fd://{}", fd),
+        }
+    }
+}
+
+impl Transport {
+    /// Connect to the server, binding the local end of a TCP connection to
+    /// `local_addr` if given
+    pub async fn connect(&self, local_addr: Option<IpAddr>) -> io::Result<Box<dyn Stream>> {
+        Ok(match self {
+            Transport::Tcp(address) => match local_addr {
+                Some(local_addr) => Box::new(connect_tcp_from(address, local_addr).await?),
+                None => Box::new(TcpStream::connect(address).await?),
+            },
+            Transport::Unix(path) => Box::new(UnixStream::connect(path).await?),
+            Transport::Abstract(name) => Box::new(connect_abstract(name)?),
+            Transport::Fd(fd) => inherited_stream(*fd)?,
+        })
+    }
+
+    /// Check that the address fits in a socket address, to fail with a
+    /// clearer error than connecting would.
+    pub fn validate(&self) -> Result<(), String> {
+        // Both a path and an abstract name (preceded by a NUL) get one byte
+        // less than sun_path has.
+        let max = SUN_PATH_LEN - 1;
+        match self {
+            Transport::Unix(path) if path.as_os_str().len() > max => {
+                let hint = if cfg!(target_os = "linux") {
+                    "; use a shorter path or a socket in the abstract namespace (@NAME)"
+                } else {
+                    "; use a shorter (e.g. relative) path"
+                };
+                Err(format!(
+                    "Unix socket path {} is {} bytes long, which exceeds the limit of {} bytes{}",
+                    path.display(),
+                    path.as_os_str().len(),
+                    max,
+                    hint
+                ))
+            }
+            Transport::Abstract(name) if name.len() > max => Err(format!(
+                "Abstract Unix socket name @{} is {} bytes long, which exceeds the limit of {} bytes",
+                name,
+                name.len(),
+                max
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Connect to `address` over TCP from `local_addr`, trying each of the
+/// addresses it resolves to of the same family in turn
+async fn connect_tcp_from(address: &str, local_addr: IpAddr) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for remote_addr in net::lookup_host(address).await?.filter(|a| { a.is_ipv4() == local_addr.is_ipv4() }) {
+        let socket = if remote_addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+        socket.bind(SocketAddr::new(local_addr, 0)).map_err(|e| {
+            io::Error::new(e.kind(), format!("Failed to bind to local address {}: {}", local_addr, e))
+        })?;
+        match socket.connect(remote_addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("{} has no address of the same family as local address {}", address, local_addr)
+        )
+    }))
+}
+
+/// Connect to a Unix domain socket in the abstract namespace
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &str) -> io::Result<UnixStream> {
+    use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+    let address = SocketAddr::from_abstract_name(name)?;
+    let stream = std::os::unix::net::UnixStream::connect_addr(&address)?;
+    stream.set_nonblocking(true)?;
+    UnixStream::from_std(stream)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(name: &str) -> io::Result<UnixStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Cannot connect to @{}: abstract Unix sockets are only supported on Linux", name)
+    ))
+}
+
+/// Wrap an inherited socket in a stream of the matching type
+///
+/// The descriptor is duplicated, so it remains usable when the connection
+/// is retried. It must be a connected socket: a listening one, as passed by
+/// systemd for a socket unit without Accept=yes, is rejected.
+fn inherited_stream(fd: RawFd) -> io::Result<Box<dyn Stream>> {
+    // SAFETY: The descriptor is only borrowed for the duration of the dup,
+    // and an invalid descriptor makes the dup fail with EBADF.
+    let owned_fd = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+
+    let stream = std::os::unix::net::UnixStream::from(owned_fd);
+    if stream.local_addr().is_ok() {
+        check_connected(fd, stream.peer_addr().err())?;
+        stream.set_nonblocking(true)?;
+        return Ok(Box::new(UnixStream::from_std(stream)?));
+    }
+
+    let stream = std::net::TcpStream::from(OwnedFd::from(stream));
+    check_connected(fd, stream.peer_addr().err())?;
+    stream.set_nonblocking(true)?;
+    Ok(Box::new(TcpStream::from_std(stream)?))
+}
+
+/// Fail if getting the peer address of inherited socket `fd` failed,
+/// because it has no peer
+fn check_connected(fd: RawFd, peer_addr_error: Option<io::Error>) -> io::Result<()> {
+    match peer_addr_error {
+        Some(e) if e.kind() == io::ErrorKind::NotConnected => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "File descriptor {} is not a connected socket; if it was passed by systemd, \
+                    set Accept=yes in the socket unit",
+                fd
+            )
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Decide how to reach the FastCGI server at `address`.
+///
+/// Both `HOST:PORT` and `[IPV6]:PORT` are considered TCP addresses, provided
+/// that PORT is numeric, and `fd://N` refers to inherited file descriptor N.
+/// Anything else is taken to be a Unix domain socket, see `unix_transport`.
+pub fn resolve_transport(address: &str) -> Transport {
+    if let Some(fd) = address.strip_prefix("fd://").and_then(|n| { n.parse().ok() }) {
+        Transport::Fd(fd)
+    } else if is_tcp_address(address) && !address.starts_with('@') {
+        Transport::Tcp(address.to_string())
+    } else {
+        unix_transport(address)
+    }
+}
+
+/// Interpret `address` as a TCP address, appending the default port if it
+/// has none.
+pub fn tcp_transport(address: &str) -> Transport {
+    if is_tcp_address(address) {
+        Transport::Tcp(address.to_string())
+    } else if address.contains(':') && !address.starts_with('[') {
+        Transport::Tcp(format!("[{}]:{}", address, DEFAULT_TCP_PORT))
+    } else {
+        Transport::Tcp(format!("{}:{}", address, DEFAULT_TCP_PORT))
+    }
+}
+
+/// Interpret `address` as a Unix domain socket: a name in the abstract
+/// namespace if it starts with '@', a path otherwise.
+pub fn unix_transport(address: &str) -> Transport {
+    match address.strip_prefix('@') {
+        Some(name) => Transport::Abstract(name.to_string()),
+        None => Transport::Unix(PathBuf::from(address)),
+    }
+}
+
+/// Find the socket passed by systemd socket activation, if any.
+///
+/// The LISTEN_FDS variable is ignored unless LISTEN_PID matches this
+/// process, as it may have been meant for a parent process instead. If more
+/// than one socket was passed, the first one is used. The socket unit must
+/// have Accept=yes, so the socket passed is a connected one.
+pub fn socket_activation_transport() -> Result<Option<Transport>, String> {
+    let listen_pid = env::var("LISTEN_PID").ok();
+    let listen_fds = env::var("LISTEN_FDS").ok();
+    let fd = parse_listen_fds(listen_pid.as_deref(), listen_fds.as_deref(), process::id())?;
+    Ok(fd.map(Transport::Fd))
+}
+
+fn parse_listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Result<Option<RawFd>, String> {
+    let Some(listen_fds) = listen_fds else {
+        return Ok(None);
+    };
+
+    if listen_pid.and_then(|p| { p.parse::<u32>().ok() }) != Some(pid) {
+        return Ok(None);
+    }
+
+    match listen_fds.parse::<u32>() {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(SD_LISTEN_FDS_START)),
+        Err(_) => Err(format!("Invalid LISTEN_FDS value \"{}\"", listen_fds)),
+    }
+}
+
+fn is_tcp_address(address: &str) -> bool {
+    let Some((host, port)) = address.rsplit_once(':') else {
+        return false;
+    };
+
+    if port.is_empty() || port.parse::<u16>().is_err() {
+        return false;
+    }
+
+    if let Some(ipv6) = host.strip_prefix('[') {
+        // Bracketed IPv6 literal, e.g. [::1]:9000
+        ipv6.strip_suffix(']').is_some_and(|h| { !h.is_empty() && !h.contains(['[', ']', '/']) })
+    } else {
+        // A bare IPv6 literal without brackets is ambiguous, so a colon or
+        // slash in the host part means this is not a TCP address.
+        !host.is_empty() && !host.contains([':', '/'])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_tcp_addresses() {
+        assert_eq!(resolve_transport("127.0.0.1:9000"), Transport::Tcp("127.0.0.1:9000".to_string()));
+        assert_eq!(resolve_transport("[::1]:9000"), Transport::Tcp("[::1]:9000".to_string()));
+        assert_eq!(resolve_transport("localhost:9000"), Transport::Tcp("localhost:9000".to_string()));
+    }
+
+    #[test]
+    fn resolves_unix_sockets() {
+        assert_eq!(resolve_transport("/run/php.sock"), Transport::Unix(PathBuf::from("/run/php.sock")));
+        assert_eq!(resolve_transport("./relative.sock"), Transport::Unix(PathBuf::from("./relative.sock")));
+    }
+
+    #[test]
+    fn rejects_ambiguous_tcp_addresses() {
+        // A bare IPv6 literal, a non-numeric port and a path with a colon
+        assert!(!is_tcp_address("::1:9000"));
+        assert!(!is_tcp_address("localhost:http"));
+        assert!(!is_tcp_address("./run/a:9000"));
+        assert!(!is_tcp_address("[]:9000"));
+        assert!(!is_tcp_address("[::1]:"));
+        assert!(!is_tcp_address("localhost"));
+    }
+
+    #[test]
+    fn resolves_abstract_sockets() {
+        assert_eq!(resolve_transport("@php-fpm"), Transport::Abstract("php-fpm".to_string()));
+        assert_eq!(Transport::Abstract("php-fpm".to_string()).to_string(), "@php-fpm");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn connects_to_abstract_socket() {
+        use std::os::{linux::net::SocketAddrExt, unix::net::{SocketAddr, UnixListener}};
+
+        let name = format!("fcgi-abstract-{}", process::id());
+        let _listener = UnixListener::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+        assert!(connect_abstract(&name).is_ok());
+        assert!(connect_abstract(&format!("{}-missing", name)).is_err());
+    }
+
+    #[test]
+    fn resolves_inherited_sockets() {
+        assert_eq!(resolve_transport("fd://3"), Transport::Fd(3));
+    }
+
+    #[test]
+    fn parses_listen_fds() {
+        assert_eq!(parse_listen_fds(Some("42"), Some("1"), 42), Ok(Some(SD_LISTEN_FDS_START)));
+        assert_eq!(parse_listen_fds(Some("42"), Some("2"), 42), Ok(Some(SD_LISTEN_FDS_START)));
+        assert_eq!(parse_listen_fds(Some("42"), Some("0"), 42), Ok(None));
+        assert_eq!(parse_listen_fds(Some("42"), None, 42), Ok(None));
+        assert!(parse_listen_fds(Some("42"), Some("x"), 42).is_err());
+    }
+
+    #[test]
+    fn ignores_listen_fds_for_other_processes() {
+        assert_eq!(parse_listen_fds(Some("41"), Some("1"), 42), Ok(None));
+        assert_eq!(parse_listen_fds(None, Some("1"), 42), Ok(None));
+        assert_eq!(parse_listen_fds(Some("x"), Some("1"), 42), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn wraps_connected_socket() {
+        use std::os::fd::AsRawFd;
+
+        let (ours, _theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+        assert!(inherited_stream(ours.as_raw_fd()).is_ok());
+    }
+
+    #[test]
+    fn rejects_listening_socket() {
+        use std::os::fd::AsRawFd;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let error = inherited_stream(listener.as_raw_fd()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("Accept=yes"));
+
+        let path = env::temp_dir().join(format!("fcgi-listen-{}.sock", process::id()));
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(inherited_stream(listener.as_raw_fd()).is_err());
+    }
+
+    #[test]
+    fn tcp_transport_appends_default_port() {
+        assert_eq!(tcp_transport("localhost"), Transport::Tcp("localhost:9000".to_string()));
+        assert_eq!(tcp_transport("::1"), Transport::Tcp("[::1]:9000".to_string()));
+        assert_eq!(tcp_transport("[::1]:9001"), Transport::Tcp("[::1]:9001".to_string()));
+    }
+
+    #[test]
+    fn validates_socket_path_length() {
+        assert!(Transport::Unix(PathBuf::from("/run/php.sock")).validate().is_ok());
+        assert!(Transport::Unix(PathBuf::from("/".repeat(SUN_PATH_LEN))).validate().is_err());
+        assert!(Transport::Abstract("a".repeat(SUN_PATH_LEN)).validate().is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::ops::RangeInclusive;
+
+/// A URL with placeholders for a number, as given to --url-template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlTemplate {
+    /// Literal text, each piece but the last followed by a placeholder
+    pieces: Vec<String>,
+    /// Minimum width of each placeholder, padded with zeros
+    widths: Vec<usize>,
+}
+
+/// Parse a template containing at least one placeholder, either {} or {:0N}
+/// to pad the number with zeros to N digits
+pub fn parse_url_template(s: &str) -> Result<UrlTemplate, String> {
+    let mut pieces = Vec::new();
+    let mut widths = Vec::new();
+    let mut rest = s;
+
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| { start + end })
+            .ok_or_else(|| { format!("Unterminated placeholder in URL template \"{}\"", s) })?;
+
+        let width = match &rest[start + 1..end] {
+            "" => 0,
+            spec => spec
+                .strip_prefix(":0")
+                .and_then(|w| { w.parse().ok() })
+                .ok_or_else(|| { format!("Invalid placeholder \"{{{}}}\", expected \"{{}}\" or \"{{:0N}}\"", spec) })?,
+        };
+
+        pieces.push(rest[..start].to_string());
+        widths.push(width);
+        rest = &rest[end + 1..];
+    }
+
+    if widths.is_empty() {
+        return Err(format!("URL template \"{}\" contains no placeholder", s));
+    }
+    pieces.push(rest.to_string());
+
+    Ok(UrlTemplate { pieces, widths })
+}
+
+impl UrlTemplate {
+    /// Substitute `n` for all placeholders
+    pub fn expand(&self, n: u64) -> String {
+        let mut out = String::new();
+        for (piece, width) in self.pieces.iter().zip(self.widths.iter()) {
+            out.push_str(piece);
+            out.push_str(&format!("{:0width$}", n, width = width));
+        }
+        out.push_str(self.pieces.last().map_or("", String::as_str));
+        out
+    }
+}
+
+/// Parse START-END, both inclusive
+pub fn parse_range(s: &str) -> Result<RangeInclusive<u64>, String> {
+    let invalid = || { format!("Invalid range \"{}\", expected \"START-END\"", s) };
+    let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+    let start: u64 = start.trim().parse().map_err(|_| { invalid() })?;
+    let end: u64 = end.trim().parse().map_err(|_| { invalid() })?;
+
+    if start > end {
+        return Err(format!("Invalid range \"{}\", START must not exceed END", s));
+    }
+
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_placeholders() {
+        let template = parse_url_template("http://localhost/item/{}?page={}").unwrap();
+        assert_eq!(template.expand(7), "http://localhost/item/7?page=7");
+    }
+
+    #[test]
+    fn pads_with_zeros() {
+        let template = parse_url_template("http://localhost/item/{:03}").unwrap();
+        assert_eq!(template.expand(7), "http://localhost/item/007");
+        assert_eq!(template.expand(1234), "http://localhost/item/1234");
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(parse_url_template("http://localhost/item").is_err());
+        assert!(parse_url_template("http://localhost/{").is_err());
+        assert!(parse_url_template("http://localhost/{:3}").is_err());
+        assert!(parse_url_template("http://localhost/{x}").is_err());
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("1-100"), Ok(1..=100));
+        assert_eq!(parse_range("5-5"), Ok(5..=5));
+        assert!(parse_range("10-1").is_err());
+        assert!(parse_range("1").is_err());
+        assert!(parse_range("a-b").is_err());
+        assert!(parse_range("-1-5").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::headers::Headers;
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant}
+};
+use url::Url;
+
+/// Measurements taken during a request, for -w, --write-out
+#[derive(Debug, Clone)]
+pub struct Stats {
+    start: Instant,
+    pub time_connect: Option<Duration>,
+    pub time_starttransfer: Option<Duration>,
+    pub time_total: Option<Duration>,
+    pub response_code: Option<u16>,
+    pub size_header: u64,
+    pub size_download: u64,
+    /// URL of a redirect to follow
+    pub redirect_url: Option<Url>,
+    pub num_redirects: u32,
+    /// Application status from FCGI_END_REQUEST
+    pub app_status: Option<u32>,
+    pub size_stderr: u64,
+    /// Parsed response headers
+    pub headers: Headers,
+    /// Response body, if captured for the report of --output-format json
+    pub body: Option<Vec<u8>>,
+    /// Output file the response body is being written to, to be removed if
+    /// the request is interrupted. Not set when appending to a file.
+    pub partial_output: Option<PathBuf>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            time_connect: None,
+            time_starttransfer: None,
+            time_total: None,
+            response_code: None,
+            size_header: 0,
+            size_download: 0,
+            redirect_url: None,
+            num_redirects: 0,
+            app_status: None,
+            size_stderr: 0,
+            headers: Headers::default(),
+            body: None,
+            partial_output: None,
+        }
+    }
+
+    /// Time passed since the request was started
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Stream to write rendered output to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Stdout,
+    Stderr,
+}
+
+/// Expand the variables in `format`, returning the output in pieces along
+/// with the stream each piece should go to.
+///
+/// Output goes to stderr, until switched by %{stdout} or %{stderr}. Like
+/// cURL, the escapes \n, \r, \t and \\ are recognized, and %% produces a
+/// single percent sign. Unknown variables expand to nothing, with a warning.
+pub fn render(format: &str, stats: &Stats) -> Vec<(Target, String)> {
+    let mut pieces = Vec::new();
+    let mut target = Target::Stderr;
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '%' if chars.peek() == Some(&'%') => {
+                chars.next();
+                out.push('%');
+            }
+            '%' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let name: String = chars.by_ref().take_while(|&c| { c != '}' }).collect();
+                match name.as_str() {
+                    "stdout" | "stderr" => {
+                        pieces.push((target, std::mem::take(&mut out)));
+                        target = if name == "stdout" { Target::Stdout } else { Target::Stderr };
+                    }
+                    _ => match expand(&name, stats) {
+                        Some(value) => out.push_str(&value),
+                        None => eprintln!("Warning: Unknown --write-out variable \"{}\"", name),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = match chars.peek() {
+                    Some('n') => Some('\n'),
+                    Some('r') => Some('\r'),
+                    Some('t') => Some('\t'),
+                    Some('\\') => Some('\\'),
+                    _ => None,
+                };
+                if let Some(escaped) = escaped {
+                    chars.next();
+                    out.push(escaped);
+                } else {
+                    out.push('\\');
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+
+    pieces.push((target, out));
+    pieces.retain(|(_, s)| { !s.is_empty() });
+    pieces
+}
+
+fn expand(name: &str, stats: &Stats) -> Option<String> {
+    let seconds = |d: Option<Duration>| { format!("{:.6}", d.unwrap_or_default().as_secs_f64()) };
+    Some(match name {
+        "time_connect" => seconds(stats.time_connect),
+        "time_starttransfer" => seconds(stats.time_starttransfer),
+        "time_total" => seconds(stats.time_total),
+        "response_code" | "http_code" => format!("{:03}", stats.response_code.unwrap_or(0)),
+        "size_header" => stats.size_header.to_string(),
+        "size_download" => stats.size_download.to_string(),
+        "num_redirects" => stats.num_redirects.to_string(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> Stats {
+        let mut stats = Stats::new();
+        stats.response_code = Some(404);
+        stats.size_download = 1234;
+        stats.num_redirects = 2;
+        stats.time_total = Some(Duration::from_millis(1500));
+        stats
+    }
+
+    #[test]
+    fn expands_variables() {
+        assert_eq!(
+            render("%{response_code} %{http_code} %{size_download} %{num_redirects} %{time_total}", &stats()),
+            [(Target::Stderr, "404 404 1234 2 1.500000".to_string())]
+        );
+        assert_eq!(render("%{response_code}", &Stats::new()), [(Target::Stderr, "000".to_string())]);
+    }
+
+    #[test]
+    fn switches_streams() {
+        assert_eq!(
+            render("a%{stdout}b%{stderr}c", &stats()),
+            [(Target::Stderr, "a".to_string()), (Target::Stdout, "b".to_string()), (Target::Stderr, "c".to_string())]
+        );
+    }
+
+    #[test]
+    fn expands_escapes() {
+        assert_eq!(render(r"a\nb\tc\\n\x", &stats()), [(Target::Stderr, "a\nb\tc\\n\\x".to_string())]);
+    }
+
+    #[test]
+    fn handles_unknown_and_escaped_variables() {
+        assert_eq!(render("[%{no_such_variable}]", &stats()), [(Target::Stderr, "[]".to_string())]);
+        assert_eq!(render("%%{response_code} 100%", &stats()), [(Target::Stderr, "%{response_code} 100%".to_string())]);
+    }
+}
@@ -0,0 +1,494 @@
+//! Runs the fcgi binary against a minimal FastCGI application running in the
+//! test itself.
+
+mod common;
+
+use common::{listen, serve_one};
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+    time::{Duration, Instant}
+};
+use tokio::task;
+
+async fn fcgi(args: &[&str]) -> Output {
+    fcgi_with_env(args, &[], b"").await
+}
+
+/// Run fcgi with variables `vars` added to the environment, and `stdin` as
+/// its input
+async fn fcgi_with_env(args: &[&str], vars: &[(&str, &str)], stdin: &[u8]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_fcgi"));
+    command
+        .args(args)
+        .arg("--no-config")
+        .env_remove("FCGI_ADDRESS")
+        .envs(vars.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let stdin = stdin.to_vec();
+    task::spawn_blocking(move || {
+        let mut child = command.spawn().unwrap();
+        child.stdin.take().unwrap().write_all(&stdin).unwrap();
+        child.wait_with_output().unwrap()
+    }).await.unwrap()
+}
+
+#[tokio::test]
+async fn sends_cookies_along_redirects() {
+    let jar = std::env::temp_dir().join(format!("fcgi-cookies-{}.txt", std::process::id()));
+    std::fs::write(&jar, "localhost\tFALSE\t/\tFALSE\t0\tkept\tyes\n").unwrap();
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        let first = serve_one(
+            &listener,
+            b"Status: 302 Found\r\nLocation: /next\r\nSet-Cookie: sid=1; Path=/\r\nSet-Cookie: evil=1; Domain=example.com\r\n\r\n",
+            b"",
+            0
+        ).await;
+        let second = serve_one(&listener, b"Set-Cookie: step=2; Path=/\r\n\r\ndone", b"", 0).await;
+        (first, second)
+    });
+
+    let jar_arg = jar.to_str().unwrap();
+    let output = fcgi(&["-L", "-b", jar_arg, "-c", jar_arg, &address, "http://localhost/start"]).await;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"done");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("domain example.com does not match host localhost"));
+    let (first, second) = server.await.unwrap();
+    assert_eq!(first.params["HTTP_COOKIE"], "kept=yes");
+    assert_eq!(second.params["REQUEST_URI"], "/next");
+    assert_eq!(second.params["HTTP_COOKIE"], "kept=yes; sid=1");
+
+    let contents = std::fs::read_to_string(&jar).unwrap();
+    std::fs::remove_file(&jar).unwrap();
+    let names: Vec<&str> = contents
+        .lines()
+        .filter(|l| { !l.starts_with('#') && !l.is_empty() })
+        .map(|l| { l.rsplit('\t').nth(1).unwrap() })
+        .collect();
+    assert_eq!(names, ["kept", "sid", "step"]);
+}
+
+#[tokio::test]
+async fn cgi_mode_forwards_environment() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        serve_one(&listener, b"Status: 201 Created\r\nContent-Type: text/plain\r\n\r\nok", b"", 0).await
+    });
+
+    let vars = [
+        ("GATEWAY_INTERFACE", "CGI/1.1"),
+        ("REQUEST_METHOD", "POST"),
+        ("CONTENT_LENGTH", "5"),
+        ("SCRIPT_NAME", "/app.php"),
+        ("SCRIPT_FILENAME", "/srv/app.php"),
+        ("DOCUMENT_ROOT", "/srv"),
+        ("REQUEST_URI", "/app.php/x?y=1"),
+        ("PATH_INFO", "/x"),
+        ("QUERY_STRING", "y=1"),
+        ("HTTPS", "on"),
+        ("REDIRECT_STATUS", "200"),
+        ("HTTP_X_TEST", "1"),
+        ("FCGI_TEST_SECRET", "leaked"),
+    ];
+    let output = fcgi_with_env(&["--cgi", &address], &vars, b"a=b&c").await;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Status: 201 Created\r\nContent-Type: text/plain\r\n\r\nok");
+    let received = server.await.unwrap();
+    assert_eq!(received.stdin, b"a=b&c");
+    for (name, value) in vars.iter().filter(|(name, _)| { *name != "FCGI_TEST_SECRET" }) {
+        assert_eq!(received.params.get(*name).map(String::as_str), Some(*value), "{}", name);
+    }
+    assert!(!received.params.contains_key("FCGI_TEST_SECRET"));
+    assert!(!received.params.contains_key("PATH"));
+}
+
+#[tokio::test]
+async fn forwards_stderr_after_failing_early() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move { serve_one(&listener, b"Status: 500\r\n\r\n", b"stack trace\n", 0).await });
+
+    let output = fcgi(&["-f", &address, "http://localhost/"]).await;
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "stack trace\nService returned an error response (code: 500)\n"
+    );
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn writes_stderr_to_stdout_in_order() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        serve_one(&listener, b"Content-Type: text/plain\r\n\r\nbody\n", b"warning\n", 0).await
+    });
+
+    let output = fcgi(&["--stderr", "stdout", "--stderr-prefix", "! ", &address, "http://localhost/"]).await;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"body\n! warning\n");
+    assert!(output.stderr.is_empty());
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn follows_two_redirects_with_stdin_body() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        let first = serve_one(&listener, b"Status: 301 Moved Permanently\r\nLocation: /b\r\n\r\n", b"", 0).await;
+        let second = serve_one(&listener, b"Status: 307 Temporary Redirect\r\nLocation: http://localhost/c\r\n\r\n", b"", 0).await;
+        let third = serve_one(&listener, b"Content-Type: text/plain\r\n\r\ndone", b"", 0).await;
+        [first, second, third]
+    });
+
+    let output = fcgi_with_env(&["-L", "-X", "PUT", "--data-file", "-", &address, "http://localhost/a"], &[], b"payload").await;
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, b"done");
+    let received = server.await.unwrap();
+    for (received, uri) in received.iter().zip(["/a", "/b", "/c"]) {
+        assert_eq!(received.params["REQUEST_URI"], uri);
+        assert_eq!(received.params["REQUEST_METHOD"], "PUT");
+        assert_eq!(received.params["CONTENT_LENGTH"], "7");
+        assert_eq!(received.stdin, b"payload");
+    }
+}
+
+#[tokio::test]
+async fn stops_at_max_redirs() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        serve_one(&listener, b"Status: 302 Found\r\nLocation: /b\r\n\r\n", b"", 0).await;
+        serve_one(&listener, b"Status: 302 Found\r\nLocation: /c\r\n\r\n", b"", 0).await;
+    });
+
+    let output = fcgi(&["-L", "--max-redirs", "1", &address, "http://localhost/a"]).await;
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Maximum number of redirects (1) followed\n");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn reports_cause_of_errors() {
+    let output = fcgi(&["--url-file", "/nonexistent", "127.0.0.1:9000"]).await;
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Failed to read URL file /nonexistent: No such file or directory"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn reports_missing_data_file_before_connecting() {
+    // Nothing listens on the port once the listener is dropped
+    let (listener, address) = listen().await;
+    drop(listener);
+
+    let output = fcgi(&["--data-file", "/nonexistent", &address, "http://localhost/"]).await;
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Failed to open data file /nonexistent: No such file or directory"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn reports_decompression_error_over_length_mismatch() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        serve_one(&listener, b"Content-Encoding: gzip\r\nContent-Length: 100\r\n\r\nnot gzip", b"", 0).await
+    });
+
+    let output = fcgi(&["--decompress", "--strict-content-length", &address, "http://localhost/"]).await;
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Failed to decompress response body"), "{}", stderr);
+    assert!(!stderr.contains("Content-Length"), "{}", stderr);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn fails_with_status_message() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move { serve_one(&listener, b"Status: 500\r\n\r\n", b"", 0).await });
+
+    let output = fcgi(&["-f", &address, "http://localhost/"]).await;
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Service returned an error response (code: 500)\n");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn fails_from_status_threshold() {
+    for (args, status, exit_code) in [
+        (&["-f"][..], 399, 0),
+        (&["-f"][..], 400, 1),
+        (&["-f"][..], 401, 1),
+        (&["--fail-with", "401"][..], 400, 0),
+        (&["--fail-with", "401"][..], 401, 1),
+    ] {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move {
+            serve_one(&listener, format!("Status: {}\r\n\r\n", status).as_bytes(), b"", 0).await
+        });
+
+        let mut args = args.to_vec();
+        args.extend([address.as_str(), "http://localhost/"]);
+        let output = fcgi(&args).await;
+
+        assert_eq!(output.status.code(), Some(exit_code), "{:?} with status {}", args, status);
+        server.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn max_time_bounds_request() {
+    // The connection is completed by the kernel, but never accepted
+    let (_listener, address) = listen().await;
+
+    let start = Instant::now();
+    let output = fcgi(&["--max-time", "0.5", &address, "http://localhost/"]).await;
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("Request to {} did not complete within 0.5 seconds", address)), "{}", stderr);
+}
+
+#[tokio::test]
+async fn decompresses_gzip_body() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        let mut response = b"Content-Type: text/plain\r\nContent-Encoding: gzip\r\n\r\n".to_vec();
+        response.extend_from_slice(
+            b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xcb\x48\xcd\xc9\xc9\xd7\x51\x28\xcf\x2f\xca\x49\x01\x00\x3a\x72\xab\xff\x0c\x00\x00\x00"
+        );
+        serve_one(&listener, &response, b"", 0).await
+    });
+
+    let output = fcgi(&["--decompress", "-i", &address, "http://localhost/"]).await;
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, b"Content-Type: text/plain\r\n\r\nhello, world");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn outputs_nph_status_line() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        serve_one(&listener, b"Status: 404 Not Found\r\nContent-Type: text/plain\r\n\r\nmissing", b"", 0).await
+    });
+
+    let output = fcgi(&["--nph", &address, "http://localhost/missing"]).await;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\n\r\nmissing");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn nph_defaults_to_200_ok() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move { serve_one(&listener, b"Content-Type: text/plain\n\nok", b"", 0).await });
+
+    let output = fcgi(&["--nph", &address, "http://localhost/"]).await;
+
+    assert_eq!(output.stdout, b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nok");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn aborts_at_max_filesize() {
+    let path = std::env::temp_dir().join(format!("fcgi-max-filesize-{}.txt", std::process::id()));
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        let mut response = b"Content-Type: text/plain\r\n\r\n".to_vec();
+        response.extend_from_slice(&[b'x'; 100]);
+        serve_one(&listener, &response, b"", 0).await
+    });
+
+    let output = fcgi(&["--max-filesize", "10", "-o", path.to_str().unwrap(), &address, "http://localhost/"]).await;
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Response body exceeds the maximum file size of 10 bytes"), "{}", stderr);
+    assert!(!path.exists());
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn fails_on_stderr() {
+    for (args, exit_code) in [(&[][..], 0), (&["--fail-on-stderr"][..], 1)] {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move {
+            serve_one(&listener, b"Content-Type: text/plain\r\n\r\nok", b"PHP Warning\n", 0).await
+        });
+
+        let mut args = args.to_vec();
+        args.extend([address.as_str(), "http://localhost/"]);
+        let output = fcgi(&args).await;
+
+        assert_eq!(output.status.code(), Some(exit_code), "{:?}", args);
+        assert_eq!(output.stdout, b"ok");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with("PHP Warning\n"), "{}", stderr);
+        assert_eq!(stderr.contains("Application wrote 12 bytes to FCGI_STDERR"), exit_code == 1, "{}", stderr);
+        server.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn exits_with_app_status() {
+    for (args, app_status, exit_code) in [
+        (&[][..], 3, 0),
+        (&["--appstatus-exit"][..], 0, 0),
+        (&["--appstatus-exit"][..], 3, 3),
+        (&["--appstatus-exit"][..], 300, 255),
+    ] {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move {
+            serve_one(&listener, b"Content-Type: text/plain\r\n\r\n", b"", app_status).await
+        });
+
+        let mut args = args.to_vec();
+        args.extend([address.as_str(), "http://localhost/"]);
+        let output = fcgi(&args).await;
+
+        assert_eq!(output.status.code(), Some(exit_code), "{:?} with appStatus {}", args, app_status);
+        server.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn traces_begin_request() {
+    let path = std::env::temp_dir().join(format!("fcgi-trace-{}.txt", std::process::id()));
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move { serve_one(&listener, b"Content-Type: text/plain\r\n\r\n", b"", 0).await });
+
+    let output = fcgi(&["--trace", path.to_str().unwrap(), &address, "http://localhost/"]).await;
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let trace = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    // Role FCGI_RESPONDER, no flags
+    assert!(
+        trace.starts_with("=> Send FCGI_BEGIN_REQUEST (1), request id 1, 8 bytes\n0000: 00 01 00 00 00 00 00 00"),
+        "{}",
+        trace
+    );
+    assert!(trace.contains("<= Recv FCGI_END_REQUEST (3), request id 1, 8 bytes\n"), "{}", trace);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn dry_run_prints_summary() {
+    let output = fcgi(&["--dry-run", "--data", "a=b", "127.0.0.1:9000", "http://localhost/index.php?x=1"]).await;
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "Address: 127.0.0.1:9000\n\
+            Parameters:\n  \
+            CONTENT_LENGTH=3\n  \
+            CONTENT_TYPE=application/x-www-form-urlencoded\n  \
+            GATEWAY_INTERFACE=CGI/1.1\n  \
+            HTTP_ACCEPT_ENCODING=gzip, deflate\n  \
+            HTTP_HOST=localhost\n  \
+            PATH_INFO=/index.php\n  \
+            QUERY_STRING=x=1\n  \
+            REMOTE_ADDR=127.0.0.1\n  \
+            REQUEST_METHOD=GET\n  \
+            REQUEST_SCHEME=http\n  \
+            REQUEST_URI=/index.php?x=1\n  \
+            SERVER_NAME=localhost\n  \
+            SERVER_PORT=80\n  \
+            SERVER_PROTOCOL=HTTP/1.1\n  \
+            SERVER_SOFTWARE=fcgi-cli/{}\n\
+            Body: 3 bytes from command line\n\
+            Output: stdout\n\
+            Stderr: stderr\n",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+
+    let output = fcgi(&["--dry-run", "-O", "127.0.0.1:9000", "http://localhost/"]).await;
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Cannot derive an output file name"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn generates_completions() {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = fcgi(&["--generate-completions", shell]).await;
+
+        assert!(output.status.success(), "{}: {}", shell, String::from_utf8_lossy(&output.stderr));
+        let script = String::from_utf8_lossy(&output.stdout);
+        assert!(script.contains("data-file"), "{}: {}", shell, script);
+    }
+}
+
+#[tokio::test]
+async fn reads_body_unless_get_or_head() {
+    // Stdin is read without --data-file or --data-from-stdin only if it is a
+    // regular file, so its length is known
+    let path = std::env::temp_dir().join(format!("fcgi-method-body-{}.txt", std::process::id()));
+    std::fs::write(&path, b"name=value").unwrap();
+
+    for (method, body) in [("post", &b"name=value"[..]), ("head", &b""[..]), ("get", &b""[..])] {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move { serve_one(&listener, b"Content-Type: text/plain\r\n\r\n", b"", 0).await });
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fcgi"));
+        command
+            .args(["--no-config", "-X", method, &address, "http://localhost/"])
+            .env_remove("FCGI_ADDRESS")
+            .stdin(std::fs::File::open(&path).unwrap());
+        let output = task::spawn_blocking(move || { command.output().unwrap() }).await.unwrap();
+
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let received = server.await.unwrap();
+        assert_eq!(received.params["REQUEST_METHOD"], method.to_ascii_uppercase());
+        assert_eq!(received.stdin, body, "{}", method);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn reads_data_from_file_or_stdin() {
+    let path = std::env::temp_dir().join(format!("fcgi-data-{}.txt", std::process::id()));
+    std::fs::write(&path, b"from=file\r\n").unwrap();
+    let at_path = format!("@{}", path.display());
+
+    for (args, stdin, body) in [
+        (&["--data", at_path.as_str()][..], &b""[..], &b"from=file"[..]),
+        (&["--data", "@-"][..], &b"from=stdin\n"[..], &b"from=stdin"[..]),
+        (&["--data-raw", "@notafile"][..], &b""[..], &b"@notafile"[..]),
+        (&["--data", "a=1", "--data-raw", "@b"][..], &b""[..], &b"a=1&@b"[..]),
+    ] {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move { serve_one(&listener, b"Content-Type: text/plain\r\n\r\n", b"", 0).await });
+
+        let mut args = args.to_vec();
+        args.extend([address.as_str(), "http://localhost/"]);
+        let output = fcgi_with_env(&args, &[], stdin).await;
+
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let received = server.await.unwrap();
+        assert_eq!(received.stdin, body, "{:?}", args);
+        assert_eq!(received.params["CONTENT_LENGTH"], body.len().to_string(), "{:?}", args);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
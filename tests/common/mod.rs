@@ -0,0 +1,181 @@
+//! A minimal FastCGI application for the tests to send requests to
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream}
+};
+
+pub const FCGI_BEGIN_REQUEST: u8 = 1;
+pub const FCGI_END_REQUEST: u8 = 3;
+pub const FCGI_PARAMS: u8 = 4;
+pub const FCGI_STDIN: u8 = 5;
+pub const FCGI_STDOUT: u8 = 6;
+pub const FCGI_STDERR: u8 = 7;
+pub const FCGI_GET_VALUES: u8 = 9;
+pub const FCGI_GET_VALUES_RESULT: u8 = 10;
+
+/// Records of a request as received by the application
+#[derive(Debug, Default)]
+pub struct Received {
+    pub request_id: u16,
+    pub role: u16,
+    /// Whether FCGI_KEEP_CONN was set
+    pub keep_conn: bool,
+    pub params: HashMap<String, String>,
+    pub stdin: Vec<u8>,
+}
+
+pub async fn read_record(stream: &mut TcpStream) -> (u8, u16, Vec<u8>) {
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header).await.unwrap();
+    let request_id = u16::from_be_bytes([header[2], header[3]]);
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut content = vec![0u8; length + header[6] as usize];
+    stream.read_exact(&mut content).await.unwrap();
+    content.truncate(length);
+    (header[1], request_id, content)
+}
+
+/// Write a record, ignoring errors, as a client may hang up as soon as it
+/// has seen enough of the response
+pub async fn write_record(stream: &mut TcpStream, record_type: u8, request_id: u16, content: &[u8]) {
+    let mut record = vec![1, record_type];
+    record.extend_from_slice(&request_id.to_be_bytes());
+    record.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    record.extend_from_slice(&[0, 0]);
+    record.extend_from_slice(content);
+    let _ = stream.write_all(&record).await;
+}
+
+fn decode_length(data: &[u8], pos: &mut usize) -> usize {
+    if data[*pos] & 0x80 == 0 {
+        *pos += 1;
+        data[*pos - 1] as usize
+    } else {
+        let length = u32::from_be_bytes([data[*pos] & 0x7f, data[*pos + 1], data[*pos + 2], data[*pos + 3]]);
+        *pos += 4;
+        length as usize
+    }
+}
+
+/// Encode name-value pairs shorter than 128 bytes
+fn encode_params(pairs: &[(&str, &str)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (name, value) in pairs {
+        data.extend_from_slice(&[name.len() as u8, value.len() as u8]);
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(value.as_bytes());
+    }
+    data
+}
+
+fn decode_params(data: &[u8]) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let name_length = decode_length(data, &mut pos);
+        let value_length = decode_length(data, &mut pos);
+        let name = String::from_utf8_lossy(&data[pos..pos + name_length]).into_owned();
+        pos += name_length;
+        let value = String::from_utf8_lossy(&data[pos..pos + value_length]).into_owned();
+        pos += value_length;
+        params.insert(name, value);
+    }
+    params
+}
+
+/// Accept a single request, answer it with `stdout` and `stderr`, and
+/// return what was received
+pub async fn serve_one(listener: &TcpListener, stdout: &[u8], stderr: &[u8], app_status: u32) -> Received {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut received = Received::default();
+    let mut params = Vec::new();
+    loop {
+        let (record_type, request_id, content) = read_record(&mut stream).await;
+        match record_type {
+            FCGI_BEGIN_REQUEST => {
+                received.request_id = request_id;
+                received.role = u16::from_be_bytes([content[0], content[1]]);
+                received.keep_conn = content[2] & 1 != 0;
+            }
+            FCGI_PARAMS => params.extend(content),
+            FCGI_STDIN if content.is_empty() => break,
+            FCGI_STDIN => received.stdin.extend(content),
+            _ => panic!("unexpected record type {}", record_type),
+        }
+    }
+    received.params = decode_params(&params);
+
+    respond(&mut stream, received.request_id, stdout, stderr, app_status).await;
+    received
+}
+
+async fn respond(stream: &mut TcpStream, id: u16, stdout: &[u8], stderr: &[u8], app_status: u32) {
+    write_record(stream, FCGI_STDOUT, id, stdout).await;
+    if !stderr.is_empty() {
+        write_record(stream, FCGI_STDERR, id, stderr).await;
+        write_record(stream, FCGI_STDERR, id, &[]).await;
+    }
+    write_record(stream, FCGI_STDOUT, id, &[]).await;
+    let mut end_request = app_status.to_be_bytes().to_vec();
+    end_request.extend_from_slice(&[0; 4]);
+    write_record(stream, FCGI_END_REQUEST, id, &end_request).await;
+}
+
+/// Answer the FCGI_GET_VALUES query a client starts with, reporting
+/// `mpxs_conns` for FCGI_MPXS_CONNS
+pub async fn answer_get_values(stream: &mut TcpStream, mpxs_conns: &str) {
+    let (record_type, _, _) = read_record(stream).await;
+    assert_eq!(record_type, FCGI_GET_VALUES);
+    let values = encode_params(&[("FCGI_MPXS_CONNS", mpxs_conns)]);
+    write_record(stream, FCGI_GET_VALUES_RESULT, 0, &values).await;
+}
+
+/// Accept a single connection reporting support for multiplexing, receive
+/// `count` requests on it, and only then answer each of them with `stdout`,
+/// the last one received first. Returns what was received, by request id.
+pub async fn serve_multiplexed(listener: &TcpListener, count: usize, stdout: &[u8]) -> Vec<Received> {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    answer_get_values(&mut stream, "1").await;
+
+    let mut requests: HashMap<u16, (Received, Vec<u8>)> = HashMap::new();
+    let mut order = Vec::new();
+    while order.len() < count {
+        let (record_type, request_id, content) = read_record(&mut stream).await;
+        let (received, params) = requests.entry(request_id).or_default();
+        match record_type {
+            FCGI_BEGIN_REQUEST => {
+                received.request_id = request_id;
+                received.role = u16::from_be_bytes([content[0], content[1]]);
+                received.keep_conn = content[2] & 1 != 0;
+            }
+            FCGI_PARAMS => params.extend(content),
+            FCGI_STDIN if content.is_empty() => order.push(request_id),
+            FCGI_STDIN => received.stdin.extend(content),
+            _ => panic!("unexpected record type {}", record_type),
+        }
+    }
+
+    for &id in order.iter().rev() {
+        respond(&mut stream, id, stdout, b"", 0).await;
+    }
+
+    let mut received: Vec<_> = requests
+        .into_values()
+        .map(|(mut received, params)| {
+            received.params = decode_params(&params);
+            received
+        })
+        .collect();
+    received.sort_by_key(|r| { r.request_id });
+    received
+}
+
+/// Listen on an unused port, returning the listener and its address
+pub async fn listen() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    (listener, address)
+}
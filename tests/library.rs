@@ -0,0 +1,343 @@
+//! Sends requests with `FcgiRequestBuilder` to a minimal FastCGI application
+//! running in the test itself.
+
+mod common;
+
+use clap::Parser;
+use common::{answer_get_values, listen, read_record, serve_multiplexed, serve_one, write_record, FCGI_STDIN, FCGI_STDOUT};
+use fcgi_cli::{run, Cli, FcgiCliError, FcgiRequestBuilder, Transport};
+use std::{fs, path::PathBuf, process, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+async fn listen_tcp() -> (tokio::net::TcpListener, Transport) {
+    let (listener, address) = listen().await;
+    (listener, Transport::Tcp(address))
+}
+
+/// Directory for the output files of test `name`, removed if it exists
+fn output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fcgi-cli-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[tokio::test]
+async fn sends_request_and_reads_response() {
+    let (listener, transport) = listen_tcp().await;
+    let server = tokio::spawn(async move {
+        serve_one(
+            &listener,
+            b"Status: 201 Created\r\nContent-Type: text/plain\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\ncreated",
+            b"a warning",
+            7
+        ).await
+    });
+
+    let response = FcgiRequestBuilder::new(transport)
+        .param("REQUEST_METHOD", "POST")
+        .param("SCRIPT_FILENAME", "/srv/app.php")
+        .body("name=value")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status, 201);
+    assert_eq!(response.headers.get("content-type"), Some("text/plain"));
+    assert_eq!(response.headers.get_all("set-cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+    assert_eq!(response.body, b"created");
+    assert_eq!(response.stderr, b"a warning");
+    assert_eq!(response.app_status, Some(7));
+
+    let received = server.await.unwrap();
+    assert_eq!(received.request_id, 1);
+    assert_eq!(received.role, 1);
+    assert_eq!(received.stdin, b"name=value");
+    assert_eq!(received.params["REQUEST_METHOD"], "POST");
+    assert_eq!(received.params["SCRIPT_FILENAME"], "/srv/app.php");
+    assert_eq!(received.params["CONTENT_LENGTH"], "10");
+    assert_eq!(received.params["GATEWAY_INTERFACE"], "CGI/1.1");
+}
+
+#[tokio::test]
+async fn defaults_to_status_200() {
+    let (listener, transport) = listen_tcp().await;
+    let server = tokio::spawn(async move { serve_one(&listener, b"Content-Type: text/html\r\n\r\n<p>", b"", 0).await });
+
+    let response = FcgiRequestBuilder::new(transport).send().await.unwrap();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"<p>");
+    assert!(response.stderr.is_empty());
+    let received = server.await.unwrap();
+    assert!(!received.params.contains_key("CONTENT_LENGTH"));
+}
+
+#[tokio::test]
+async fn fails_on_malformed_header() {
+    let (listener, transport) = listen_tcp().await;
+    let server = tokio::spawn(async move { serve_one(&listener, b"not a header\r\n\r\n", b"", 0).await });
+
+    let error = FcgiRequestBuilder::new(transport).send().await.unwrap_err();
+
+    assert!(matches!(error, FcgiCliError::HeaderParse(_)));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn fails_to_connect() {
+    let (listener, transport) = listen_tcp().await;
+    drop(listener);
+
+    let error = FcgiRequestBuilder::new(transport).send().await.unwrap_err();
+
+    assert!(matches!(error, FcgiCliError::Connect(_)));
+}
+
+#[tokio::test]
+async fn expands_url_template_as_needed() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move { serve_one(&listener, b"Status: 500\r\n\r\n", b"", 0).await });
+
+    // Far more URLs than could be expanded up front
+    let cli = Cli::try_parse_from([
+        "fcgi",
+        "-f",
+        "--fail-early",
+        "--url-template",
+        "http://localhost/{}",
+        "--range",
+        "1-100000000000",
+        &address
+    ]).unwrap();
+    let error = run(cli, CancellationToken::new()).await.unwrap_err();
+
+    assert_eq!(error.to_string(), "1 of 100000000000 requests failed, 99999999999 cancelled");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn multiplexes_requests_on_one_connection() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move { serve_multiplexed(&listener, 2, b"Content-Type: text/plain\r\n\r\nok").await });
+
+    let dir = output_dir("multiplexed");
+    let cli = Cli::try_parse_from([
+        "fcgi",
+        "--reuse-connection",
+        "--parallel",
+        "2",
+        "--output-dir",
+        dir.to_str().unwrap(),
+        "--create-dirs",
+        &address,
+        "http://localhost/a",
+        "http://localhost/b"
+    ]).unwrap();
+    assert_eq!(run(cli, CancellationToken::new()).await.unwrap(), 0);
+
+    let received = server.await.unwrap();
+    assert_eq!(received.len(), 2);
+    assert_ne!(received[0].request_id, received[1].request_id);
+    assert!(received.iter().all(|r| { r.keep_conn }));
+    assert_eq!(fs::read(dir.join("a")).unwrap(), b"ok");
+    assert_eq!(fs::read(dir.join("b")).unwrap(), b"ok");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn falls_back_to_a_connection_per_request() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        answer_get_values(&mut stream, "0").await;
+        let first = serve_one(&listener, b"Content-Type: text/plain\r\n\r\nok", b"", 0).await;
+        let second = serve_one(&listener, b"Content-Type: text/plain\r\n\r\nok", b"", 0).await;
+        [first, second]
+    });
+
+    let dir = output_dir("fallback");
+    let cli = Cli::try_parse_from([
+        "fcgi",
+        "--reuse-connection",
+        "--output-dir",
+        dir.to_str().unwrap(),
+        "--create-dirs",
+        &address,
+        "http://localhost/a",
+        "http://localhost/b"
+    ]).unwrap();
+    assert_eq!(run(cli, CancellationToken::new()).await.unwrap(), 0);
+
+    for received in server.await.unwrap() {
+        assert_eq!(received.request_id, 1);
+        assert!(!received.keep_conn);
+    }
+    assert_eq!(fs::read(dir.join("a")).unwrap(), b"ok");
+    assert_eq!(fs::read(dir.join("b")).unwrap(), b"ok");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn stops_when_cancelled() {
+    // The connection is completed by the kernel, but never accepted
+    let (_listener, address) = listen().await;
+    let interrupt = CancellationToken::new();
+    let cancel = interrupt.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cancel.cancel();
+    });
+
+    let cli = Cli::try_parse_from(["fcgi", &address, "http://localhost/"]).unwrap();
+    let error = run(cli, interrupt).await.unwrap_err();
+
+    assert!(matches!(error.downcast_ref(), Some(FcgiCliError::Interrupted)));
+}
+
+#[tokio::test]
+async fn removes_partial_output_when_cancelled() {
+    let (listener, address) = listen().await;
+    let dir = output_dir("cancelled");
+    let path = dir.join("out.txt");
+    let interrupt = CancellationToken::new();
+    let cancel = interrupt.clone();
+    let written = path.clone();
+    // Send part of the body, and cancel once it is written, while the rest
+    // is still awaited
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        loop {
+            let (record_type, _, content) = read_record(&mut stream).await;
+            if record_type == FCGI_STDIN && content.is_empty() {
+                break;
+            }
+        }
+        write_record(&mut stream, FCGI_STDOUT, 1, b"Content-Type: text/plain\r\n\r\npartial").await;
+        while fs::metadata(&written).map_or(true, |m| { m.len() == 0 }) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        cancel.cancel();
+        stream
+    });
+
+    let cli = Cli::try_parse_from(["fcgi", "--create-dirs", "-o", path.to_str().unwrap(), &address, "http://localhost/"])
+        .unwrap();
+    let error = run(cli, interrupt).await.unwrap_err();
+
+    assert!(matches!(error.downcast_ref(), Some(FcgiCliError::Interrupted)));
+    assert!(!path.exists());
+    drop(server.await.unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn sets_keep_conn_flag() {
+    for (args, keep_conn) in [(&[][..], false), (&["--keepalive"][..], true)] {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move { serve_one(&listener, b"Content-Type: text/plain\r\n\r\n", b"", 0).await });
+
+        let mut args = [&["fcgi"][..], args].concat();
+        args.extend([address.as_str(), "http://localhost/"]);
+        let cli = Cli::try_parse_from(&args).unwrap();
+        assert_eq!(run(cli, CancellationToken::new()).await.unwrap(), 0);
+
+        let received = server.await.unwrap();
+        assert_eq!(received.keep_conn, keep_conn, "{:?}", args);
+    }
+}
+
+#[tokio::test]
+async fn fails_on_error_status() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move { serve_one(&listener, b"Status: 404 Not Found\r\n\r\n", b"", 0).await });
+
+    let cli = Cli::try_parse_from(["fcgi", "-f", &address, "http://localhost/missing"]).unwrap();
+    let error = run(cli, CancellationToken::new()).await.unwrap_err();
+
+    assert!(matches!(error.downcast_ref(), Some(FcgiCliError::Status(404))));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn fails_on_unusable_transport() {
+    let cli = Cli::try_parse_from(["fcgi", "--interface", "127.0.0.1", "/run/php.sock", "http://localhost/"]).unwrap();
+    let error = run(cli, CancellationToken::new()).await.unwrap_err();
+
+    assert!(matches!(error.downcast_ref(), Some(FcgiCliError::Transport(_))));
+}
+
+#[tokio::test]
+async fn cancels_other_requests_when_failing_early() {
+    let (listener, address) = listen().await;
+    // Of the two connections, only the one accepted first is answered. The
+    // other request never completes, unless cancelled.
+    let server = tokio::spawn(async move {
+        serve_one(&listener, b"Status: 500\r\n\r\n", b"", 0).await;
+        listener
+    });
+
+    let cli = Cli::try_parse_from([
+        "fcgi",
+        "-f",
+        "--fail-early",
+        "--parallel",
+        "2",
+        &address,
+        "http://localhost/a",
+        "http://localhost/b"
+    ]).unwrap();
+    let result = tokio::time::timeout(Duration::from_secs(5), run(cli, CancellationToken::new())).await;
+
+    let error = result.expect("slow request not cancelled").unwrap_err();
+    assert_eq!(error.to_string(), "1 of 2 requests failed, 1 cancelled");
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn sends_request_id() {
+    let (listener, address) = listen().await;
+    let server = tokio::spawn(async move { serve_one(&listener, b"Content-Type: text/plain\r\n\r\n", b"", 0).await });
+
+    let cli = Cli::try_parse_from(["fcgi", "--request-id", "65535", &address, "http://localhost/"]).unwrap();
+    assert_eq!(run(cli, CancellationToken::new()).await.unwrap(), 0);
+
+    assert_eq!(server.await.unwrap().request_id, 65535);
+    assert!(Cli::try_parse_from(["fcgi", "--request-id", "0", &address, "http://localhost/"]).is_err());
+    assert!(Cli::try_parse_from(["fcgi", "--request-id", "65536", &address, "http://localhost/"]).is_err());
+}
+
+#[tokio::test]
+async fn numbers_colliding_output_files() {
+    for (clobber, files) in [
+        ("--no-clobber", &[("index.html", &b"first"[..]), ("index.html.1", &b"second"[..])][..]),
+        ("--clobber", &[("index.html", &b"second"[..])][..]),
+    ] {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move {
+            serve_one(&listener, b"Content-Type: text/html\r\n\r\nfirst", b"", 0).await;
+            serve_one(&listener, b"Content-Type: text/html\r\n\r\nsecond", b"", 0).await;
+        });
+
+        let dir = output_dir(clobber.trim_start_matches('-'));
+        let cli = Cli::try_parse_from([
+            "fcgi",
+            clobber,
+            "--output-dir",
+            dir.to_str().unwrap(),
+            "--create-dirs",
+            &address,
+            "http://localhost/a/index.html",
+            "http://localhost/b/index.html"
+        ]).unwrap();
+        assert_eq!(run(cli, CancellationToken::new()).await.unwrap(), 0);
+        server.await.unwrap();
+
+        let mut names: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| { e.unwrap().file_name() }).collect();
+        names.sort();
+        assert_eq!(names, files.iter().map(|(name, _)| { name.into() }).collect::<Vec<std::ffi::OsString>>());
+        for (name, content) in files {
+            assert_eq!(fs::read(dir.join(name)).unwrap(), *content, "{} with {}", name, clobber);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}